@@ -0,0 +1,75 @@
+use moka::future::Cache;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const CAPACITY: u64 = 16_384;
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Album {
+    message_ids: Vec<i64>,
+    removed: bool,
+}
+
+// the items of an album arrive as separate updates (and may be handled by different workers), so
+// this keeps the IDs of the items seen recently, and whether the album was removed as spam
+pub struct Albums {
+    albums: Cache<(i64, String), Arc<Mutex<Album>>>,
+}
+
+impl Albums {
+    pub fn new() -> Self {
+        let albums = Cache::builder()
+            .max_capacity(CAPACITY)
+            .time_to_live(WINDOW)
+            .build();
+        Self { albums }
+    }
+
+    async fn album(&self, chat_id: i64, media_group_id: &str) -> Arc<Mutex<Album>> {
+        self.albums
+            .get_with((chat_id, media_group_id.to_string()), async {
+                Arc::default()
+            })
+            .await
+    }
+
+    // records the item and tells whether the album it belongs to was already removed as spam
+    pub async fn add(&self, chat_id: i64, media_group_id: &str, message_id: i64) -> bool {
+        let album = self.album(chat_id, media_group_id).await;
+        let Ok(mut album) = album.lock() else {
+            return false;
+        };
+        album.message_ids.push(message_id);
+        album.removed
+    }
+
+    // marks the album as removed and returns the items seen so far, so items arriving later are
+    // removed as soon as they are added
+    pub async fn remove(&self, chat_id: i64, media_group_id: &str) -> Vec<i64> {
+        let album = self.album(chat_id, media_group_id).await;
+        let Ok(mut album) = album.lock() else {
+            return vec![];
+        };
+        album.removed = true;
+        album.message_ids.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_albums() {
+        let albums = Albums::new();
+        assert!(!albums.add(-42, "13579", 1).await);
+        assert!(!albums.add(-42, "13579", 2).await);
+        assert!(!albums.add(-1, "13579", 3).await);
+        assert_eq!(albums.remove(-42, "13579").await, vec![1, 2]);
+        assert!(albums.add(-42, "13579", 4).await);
+        assert!(!albums.add(-42, "24680", 5).await);
+    }
+}
@@ -107,9 +107,13 @@ struct Evaluation {
 
 impl Evaluation {
     async fn new(embeddings: &Arc<Mutex<Embeddings>>, task: &Task, input: Input) -> Result<Self> {
-        let result =
-            is_spam_with_custom_classifier(embeddings, input.classifier, task.content.as_str())
-                .await?;
+        let result = is_spam_with_custom_classifier(
+            embeddings,
+            input.classifier,
+            task.content.as_str(),
+            &[],
+        )
+        .await?;
         let expected = task.is_spam == result.is_spam;
         let score = result.score.unwrap_or(0.0);
         let scores = result.scores;
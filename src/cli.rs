@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(name = "airnope")]
@@ -32,4 +33,11 @@ pub enum Commands {
     },
     /// Remove the bot webhook from Telegram's server
     RemoveWebhook,
+    /// Add images to the list of known spam images (only their hashes are stored), the bot
+    /// reads that list when it starts, so restart it afterwards
+    AddImage {
+        /// One or more images to add
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
 }
@@ -0,0 +1,298 @@
+use crate::{
+    modlog::{Action, Cleanup, JoinAction},
+    settings::ChatSettings,
+    storage::AsyncStorage,
+};
+use airnope::telegram::Client;
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+use tokio::time::sleep;
+
+const PREFIX: &str = "/airnope_";
+const REPLY_TTL: Duration = Duration::from_secs(15);
+const USAGE: &str = "Usage: /airnope_threshold <0.0-1.0|default>, /airnope_action <ban|mute|delete>, /airnope_shadow <on|off>, /airnope_log <chat id|off>, /airnope_join <ban|restrict|log|off>, /airnope_requests <decline|approve>, /airnope_newcomers <on|off>, /airnope_shared <on|off>, /airnope_cleanup <off|recent|all> or /airnope_status";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Threshold(Option<f32>),
+    Action(Action),
+    Shadow(bool),
+    Log(Option<i64>),
+    Join(Option<JoinAction>),
+    ApproveRequests(bool),
+    Newcomers(bool),
+    SharedBans(bool),
+    Cleanup(Cleanup),
+    Status,
+}
+
+fn on_or_off(value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(anyhow!("Expected on or off, got {value}")),
+    }
+}
+
+impl Command {
+    // returns `None` if the text is not an AirNope command at all
+    fn parse(text: &str) -> Option<Result<Self>> {
+        let mut words = text.split_whitespace();
+        let name = words.next()?.strip_prefix(PREFIX)?;
+        let name = name.split('@').next().unwrap_or(name);
+        let value = words.next();
+        if words.next().is_some() {
+            return Some(Err(anyhow!(USAGE)));
+        }
+        let command = match (name, value) {
+            ("threshold", Some("default")) => Ok(Command::Threshold(None)),
+            ("threshold", Some(value)) => match value.parse::<f32>() {
+                Ok(threshold) if threshold > 0.0 && threshold < 1.0 => {
+                    Ok(Command::Threshold(Some(threshold)))
+                }
+                _ => Err(anyhow!("Threshold must be a number between 0 and 1")),
+            },
+            ("action", Some(value)) => value.parse::<Action>().map(Command::Action),
+            ("shadow", Some(value)) => on_or_off(value).map(Command::Shadow),
+            ("log", Some("off")) => Ok(Command::Log(None)),
+            ("log", Some(value)) => value
+                .parse::<i64>()
+                .map(|id| Command::Log(Some(id)))
+                .map_err(|_| anyhow!("Expected a chat ID or off, got {value}")),
+            ("join", Some("off")) => Ok(Command::Join(None)),
+            ("join", Some(value)) => value.parse::<JoinAction>().map(|a| Command::Join(Some(a))),
+            ("requests", Some("decline")) => Ok(Command::ApproveRequests(false)),
+            ("requests", Some("approve")) => Ok(Command::ApproveRequests(true)),
+            ("newcomers", Some(value)) => on_or_off(value).map(Command::Newcomers),
+            ("shared", Some(value)) => on_or_off(value).map(Command::SharedBans),
+            ("cleanup", Some(value)) => value.parse::<Cleanup>().map(Command::Cleanup),
+            ("status", None) => Ok(Command::Status),
+            _ => Err(anyhow!(USAGE)),
+        };
+        Some(command)
+    }
+
+    fn apply(&self, settings: &mut ChatSettings) {
+        match self {
+            Command::Threshold(threshold) => settings.threshold = *threshold,
+            Command::Action(action) => settings.action = *action,
+            Command::Shadow(shadow) => settings.shadow = *shadow,
+            Command::Log(chat_id) => settings.log_chat_id = *chat_id,
+            Command::Join(action) => settings.join = *action,
+            Command::ApproveRequests(approve) => settings.approve_requests = *approve,
+            Command::Newcomers(newcomers) => settings.newcomers = *newcomers,
+            Command::SharedBans(shared_bans) => settings.shared_bans = *shared_bans,
+            Command::Cleanup(cleanup) => settings.cleanup = *cleanup,
+            Command::Status => {}
+        }
+    }
+}
+
+fn status(settings: &ChatSettings) -> String {
+    [
+        format!(
+            "Threshold: {:.2}{}",
+            settings.threshold(),
+            if settings.threshold.is_none() {
+                " (default)"
+            } else {
+                ""
+            }
+        ),
+        format!("Action: {}", settings.action),
+        format!(
+            "Shadow mode: {}",
+            if settings.shadow { "on" } else { "off" }
+        ),
+        format!(
+            "Moderation log: {}",
+            settings
+                .log_chat_id
+                .map(|id| id.to_string())
+                .unwrap_or("default".to_string())
+        ),
+        format!(
+            "New members: {}",
+            settings
+                .join
+                .map(|action| action.to_string())
+                .unwrap_or("off".to_string())
+        ),
+        format!(
+            "Join requests: {}",
+            if settings.approve_requests {
+                "decline spam, approve others"
+            } else {
+                "decline spam"
+            }
+        ),
+        format!(
+            "Newcomer scrutiny: {}",
+            if settings.newcomers { "on" } else { "off" }
+        ),
+        format!(
+            "Shared ban list: {}",
+            if settings.shared_bans { "on" } else { "off" }
+        ),
+        format!("Clean up on ban: {}", settings.cleanup),
+    ]
+    .join("\n")
+}
+
+// replies privately to the admin if possible, otherwise replies in the group and cleans up
+// shortly after, so the group does not get polluted by AirNope messages
+async fn reply(client: &Client, chat_id: i64, message_id: i64, admin_id: i64, text: &str) {
+    if let Err(e) = client.send_message(admin_id, text, None).await {
+        log::debug!("Could not reply privately to {admin_id}, replying in the group: {e}");
+        match client.send_message(chat_id, text, None).await {
+            Ok(reply_id) => {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    sleep(REPLY_TTL).await;
+                    if let Err(e) = client.delete_message(chat_id, reply_id).await {
+                        log::error!("Error deleting AirNope reply: {e}");
+                    }
+                });
+            }
+            Err(e) => log::error!("Error replying to AirNope command: {e}"),
+        }
+    }
+    if let Err(e) = client.delete_message(chat_id, message_id).await {
+        log::error!("Error deleting AirNope command: {e}");
+    }
+}
+
+// reports can only go to the admin's private chat or to a chat they are an admin of as well, so
+// admins cannot make AirNope post in chats they do not control
+async fn check_log_chat(client: &Client, log_chat_id: i64, admin_id: i64) -> Result<()> {
+    if log_chat_id == admin_id {
+        return Ok(());
+    }
+    match client.is_admin(log_chat_id, admin_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(anyhow!(
+            "Only admins of {log_chat_id} can use it as the moderation log"
+        )),
+        Err(e) => {
+            log::debug!("Error checking the admins of {log_chat_id}: {e}");
+            Err(anyhow!(
+                "Could not check the admins of {log_chat_id}, is AirNope an admin there?"
+            ))
+        }
+    }
+}
+
+// returns `true` if the message was an AirNope command sent by an admin (messages from other users
+// still go through the spam detection, so a command prefix is not a way around it)
+pub async fn handle(
+    client: &Client,
+    storage: &AsyncStorage,
+    chat_id: i64,
+    chat_title: Option<&str>,
+    message_id: i64,
+    admin_id: i64,
+    text: &str,
+) -> Result<bool> {
+    let Some(command) = Command::parse(text) else {
+        return Ok(false);
+    };
+    if !client.is_admin(chat_id, admin_id).await? {
+        log::info!("Ignoring AirNope command from non-admin {admin_id} in {chat_id}");
+        return Ok(false);
+    }
+    let command = match command {
+        Ok(Command::Log(Some(log_chat_id))) => check_log_chat(client, log_chat_id, admin_id)
+            .await
+            .map(|_| Command::Log(Some(log_chat_id))),
+        command => command,
+    };
+    let response = match command {
+        Ok(command) => {
+            let settings = storage
+                .run(move |storage| {
+                    let mut settings = storage.chat_settings(chat_id)?;
+                    command.apply(&mut settings);
+                    storage.save_chat_settings(chat_id, &settings)?;
+                    Ok(settings)
+                })
+                .await?;
+            status(&settings)
+        }
+        Err(e) => e.to_string(),
+    };
+    let title = chat_title.unwrap_or("this chat");
+    let text = format!("AirNope settings for {title}\n\n{response}");
+    reply(client, chat_id, message_id, admin_id, &text).await;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let test_cases = vec![
+            ("/airnope_threshold 0.6", Command::Threshold(Some(0.6))),
+            ("/airnope_threshold default", Command::Threshold(None)),
+            ("/airnope_action mute", Command::Action(Action::Mute)),
+            ("/airnope_shadow on", Command::Shadow(true)),
+            ("/airnope_shadow OFF", Command::Shadow(false)),
+            (
+                "/airnope_log -1002201234567",
+                Command::Log(Some(-1002201234567)),
+            ),
+            ("/airnope_log off", Command::Log(None)),
+            (
+                "/airnope_join restrict",
+                Command::Join(Some(JoinAction::Restrict)),
+            ),
+            ("/airnope_join off", Command::Join(None)),
+            ("/airnope_requests approve", Command::ApproveRequests(true)),
+            ("/airnope_newcomers on", Command::Newcomers(true)),
+            ("/airnope_shared off", Command::SharedBans(false)),
+            ("/airnope_cleanup all", Command::Cleanup(Cleanup::All)),
+            ("/airnope_status", Command::Status),
+            ("/airnope_status@AirNope_bot", Command::Status),
+        ];
+        for (text, expected) in test_cases {
+            assert_eq!(Command::parse(text).unwrap().unwrap(), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for text in [
+            "/airnope_threshold 1.5",
+            "/airnope_threshold",
+            "/airnope_action react",
+            "/airnope_shadow maybe",
+            "/airnope_status now",
+            "/airnope_join mute",
+            "/airnope_requests off",
+            "/airnope_cleanup everything",
+            "/airnope_nope",
+        ] {
+            assert!(Command::parse(text).unwrap().is_err(), "{text}");
+        }
+        for text in ["airdrop", "/start", "@AirNope_bot"] {
+            assert!(Command::parse(text).is_none(), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut settings = ChatSettings::default();
+        Command::Threshold(Some(0.6)).apply(&mut settings);
+        Command::Action(Action::Delete).apply(&mut settings);
+        Command::Shadow(true).apply(&mut settings);
+        Command::Join(Some(JoinAction::Ban)).apply(&mut settings);
+        assert_eq!(settings.threshold(), 0.6);
+        assert_eq!(settings.action, Action::Delete);
+        assert!(settings.shadow);
+        assert_eq!(
+            status(&settings),
+            "Threshold: 0.60\nAction: delete\nShadow mode: on\nModeration log: default\nNew members: ban\nJoin requests: decline spam\nNewcomer scrutiny: off\nShared ban list: off\nClean up on ban: recent"
+        );
+    }
+}
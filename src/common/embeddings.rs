@@ -1,3 +1,4 @@
+use crate::metrics::metrics;
 use anyhow::{anyhow, Result};
 use moka::future::Cache;
 use rust_bert::pipelines::sentence_embeddings::{
@@ -26,7 +27,9 @@ impl Embeddings {
         cache_key: Vec<u8>,
         text: &str,
     ) -> Result<[f32; EMBEDDINGS_SIZE]> {
+        let timer = metrics().embedding_seconds.start_timer();
         let results = self.model.encode(&[text])?;
+        timer.observe_duration();
         let vector = results
             .first()
             .ok_or(anyhow!("Error creating embedding"))?
@@ -0,0 +1,109 @@
+use crate::urls::LINK;
+use regex::Regex;
+use std::sync::LazyLock;
+
+// near-duplicates differ in at most this many bits of their fingerprints
+pub const MAX_DISTANCE: u32 = 5;
+pub const BANDS: usize = 8;
+const MIN_WORDS: usize = 6;
+const SHINGLE: usize = 4;
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+static WORD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[\p{L}\p{N}]+").unwrap());
+
+// FNV-1a, because fingerprints are persisted and need a hash that is stable across builds
+fn hash(txt: &str) -> u64 {
+    txt.bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// SimHash over sequences of characters of the words, ignoring case, punctuation, spacing, links
+// and numbers, so the same text with small edits (a different link, an extra emoji or word) ends
+// up with a similar fingerprint
+pub fn simhash(txt: &str) -> Option<u64> {
+    let words = WORD
+        .find_iter(&LINK.replace_all(txt, " "))
+        .map(|word| word.as_str().to_lowercase())
+        .filter(|word| !word.chars().all(char::is_numeric))
+        .collect::<Vec<String>>();
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+    let chars = words.join(" ").chars().collect::<Vec<char>>();
+    let mut weights = [0i32; 64];
+    for shingle in chars.windows(SHINGLE) {
+        let hash = hash(&shingle.iter().collect::<String>());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) == 0 {
+                *weight -= 1;
+            } else {
+                *weight += 1;
+            }
+        }
+    }
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |fingerprint, (bit, _)| fingerprint | (1 << bit)),
+    )
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// with up to `MAX_DISTANCE` different bits, near-duplicates have at least one identical band, so
+// bands can be used to look for candidates
+pub fn bands(fingerprint: u64) -> [u8; BANDS] {
+    fingerprint.to_le_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPAM: &str = "🎁 The $NOT airdrop is live! Claim your free tokens before the snapshot at https://claim-not.pages.dev and connect your wallet now";
+
+    #[test]
+    fn test_simhash_near_duplicates() {
+        let original = simhash(SPAM).unwrap();
+        let shouting = simhash(&SPAM.to_uppercase()).unwrap();
+        assert_eq!(original, shouting);
+        for edited in [
+            SPAM.replace("claim-not.pages.dev", "not-claim.pages.dev"),
+            SPAM.replace(
+                "https://claim-not.pages.dev",
+                "https://notcoin-rewards.xyz/claim?ref=8812",
+            ),
+            SPAM.replace("live!", "live! 🔥"),
+            SPAM.replace("live!", "live! 1️⃣"),
+            SPAM.replace("free tokens", "free NOT tokens"),
+        ] {
+            let edited = simhash(&edited).unwrap();
+            assert!(distance(original, edited) <= MAX_DISTANCE);
+        }
+        for other in [
+            "Does anyone know where I can find the minutes of yesterday's meeting about the open data portal?",
+            "Claim your spot at the workshop before Friday, the link to connect is in the group description",
+        ] {
+            assert!(distance(original, simhash(other).unwrap()) > MAX_DISTANCE);
+        }
+    }
+
+    #[test]
+    fn test_simhash_ignores_short_messages() {
+        assert_eq!(simhash("gm everyone"), None);
+    }
+
+    #[test]
+    fn test_bands() {
+        assert_eq!(
+            bands(0x0123_4567_89ab_cdef),
+            [0xef, 0xcd, 0xab, 0x89, 0x67, 0x45, 0x23, 0x01]
+        );
+    }
+}
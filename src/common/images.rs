@@ -0,0 +1,174 @@
+use crate::fingerprint::distance;
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage, ImageReader, Limits};
+use rqrr::PreparedImage;
+use std::{env, io::Cursor, path::PathBuf};
+use tokio::{fs, sync::OnceCell};
+
+const MAX_DIMENSION: u32 = 4_096;
+const MAX_ALLOC: u64 = 64 * 1024 * 1024;
+const IMAGE_HASHES_PATH: &str = "AIRNOPE_IMAGE_HASHES_PATH";
+// near-duplicates of an image (resized, recompressed, with a different caption) differ in at most
+// this many bits of their hashes
+const MAX_HASH_DISTANCE: u32 = 8;
+
+static KNOWN_IMAGES: OnceCell<KnownImages> = OnceCell::const_new();
+
+// refuses images that would take too much memory to decode, whatever their size in bytes
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_ALLOC);
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits);
+    Ok(reader.decode()?)
+}
+
+// contents of the QR codes that could be read from the image
+pub fn qr_codes(image: &DynamicImage) -> Vec<String> {
+    let gray = image.to_luma8();
+    let mut prepared = PreparedImage::prepare_from_greyscale(
+        gray.width() as usize,
+        gray.height() as usize,
+        |x, y| gray.get_pixel(x as u32, y as u32).0[0],
+    );
+    prepared
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| grid.decode().ok())
+        .map(|(_, contents)| contents)
+        .collect()
+}
+
+// dHash: whether each pixel is brighter than the next one, in a 9x8 grayscale version of the image
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+// only the hashes of known spam images are kept, never the images themselves
+#[derive(Debug, Default, PartialEq)]
+pub struct KnownImages {
+    hashes: Vec<u64>,
+}
+
+impl KnownImages {
+    // one hash per line, in hexadecimal, followed by an optional comment starting with `#`
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut hashes = vec![];
+        for line in contents.lines() {
+            let hash = line.split('#').next().unwrap_or_default().trim();
+            if hash.is_empty() {
+                continue;
+            }
+            hashes.push(
+                u64::from_str_radix(hash, 16).map_err(|_| anyhow!("Invalid image hash: {hash}"))?,
+            );
+        }
+        Ok(Self { hashes })
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        env::var(IMAGE_HASHES_PATH).ok().map(PathBuf::from)
+    }
+
+    async fn from_env() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let contents = fs::read_to_string(&path).await?;
+        let known = Self::parse(&contents)?;
+        log::info!(
+            "Using {} known spam image hashes from {}",
+            known.hashes.len(),
+            path.display()
+        );
+        Ok(known)
+    }
+
+    // loaded once from the path set in the environment, so changes to the list only take effect
+    // after a restart
+    pub async fn shared() -> Result<&'static Self> {
+        KNOWN_IMAGES.get_or_try_init(Self::from_env).await
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        self.hashes
+            .iter()
+            .any(|known| distance(hash, *known) <= MAX_HASH_DISTANCE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, ImageFormat, Luma};
+    use qrcode::{Color, QrCode};
+
+    const SCALE: u32 = 4;
+    const QUIET_ZONE: u32 = 4;
+
+    // a PNG with the QR code, as Telegram would send it
+    fn qr_code_png(contents: &str) -> Vec<u8> {
+        let code = QrCode::new(contents).unwrap();
+        let width = code.width() as u32;
+        let colors = code.to_colors();
+        let side = (width + 2 * QUIET_ZONE) * SCALE;
+        let image = GrayImage::from_fn(side, side, |x, y| {
+            let (x, y) = (x / SCALE, y / SCALE);
+            let inside = (QUIET_ZONE..QUIET_ZONE + width).contains(&x)
+                && (QUIET_ZONE..QUIET_ZONE + width).contains(&y);
+            if inside && colors[((y - QUIET_ZONE) * width + x - QUIET_ZONE) as usize] == Color::Dark
+            {
+                Luma([0])
+            } else {
+                Luma([255])
+            }
+        });
+        let mut bytes = Cursor::new(vec![]);
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_qr_codes() {
+        let image = decode(&qr_code_png("https://claim-not.pages.dev/?ref=42")).unwrap();
+        assert_eq!(
+            qr_codes(&image),
+            vec!["https://claim-not.pages.dev/?ref=42".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_dhash_of_near_duplicates() {
+        let image = decode(&qr_code_png("https://claim-not.pages.dev/?ref=42")).unwrap();
+        let resized = image.resize(image.width() / 3, image.height() / 3, FilterType::Nearest);
+        let other = decode(&qr_code_png("Does anyone have the minutes of the meeting?")).unwrap();
+        let hash = dhash(&image);
+        let known =
+            KnownImages::parse(&format!("# spam banners\n{hash:016x} # claim-not\n")).unwrap();
+        assert!(known.contains(hash));
+        assert!(known.contains(dhash(&resized)));
+        assert!(!known.contains(dhash(&other)));
+        assert!(KnownImages::parse("not a hash").is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_what_is_not_an_image() {
+        assert!(decode(b"claim your airdrop").is_err());
+    }
+}
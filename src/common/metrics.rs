@@ -0,0 +1,134 @@
+use anyhow::Result;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+
+const NAMESPACE: &str = "airnope";
+
+static METRICS: LazyLock<Metrics> =
+    LazyLock::new(|| Metrics::new().expect("Error registering metrics"));
+
+pub struct Metrics {
+    registry: Registry,
+    pub updates_received: IntCounter,
+    pub queue_depth: IntGauge,
+    pub messages_classified: IntCounter,
+    pub verdicts: IntCounterVec,
+    pub actions: IntCounterVec,
+    pub telegram_errors: IntCounterVec,
+    pub ocr: IntCounterVec,
+    pub embedding_seconds: Histogram,
+    pub handling_seconds: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let updates_received = IntCounter::with_opts(
+            Opts::new("updates_received_total", "Updates received from Telegram")
+                .namespace(NAMESPACE),
+        )?;
+        let queue_depth = IntGauge::with_opts(
+            Opts::new("queue_depth", "Updates waiting to be processed").namespace(NAMESPACE),
+        )?;
+        let messages_classified = IntCounter::with_opts(
+            Opts::new("messages_classified_total", "Messages checked for spam")
+                .namespace(NAMESPACE),
+        )?;
+        let verdicts = IntCounterVec::new(
+            Opts::new("verdicts_total", "Verdicts by the stage that decided them")
+                .namespace(NAMESPACE),
+            &["stage"],
+        )?;
+        let actions = IntCounterVec::new(
+            Opts::new(
+                "actions_total",
+                "Actions taken on messages detected as spam",
+            )
+            .namespace(NAMESPACE),
+            &["action"],
+        )?;
+        let telegram_errors = IntCounterVec::new(
+            Opts::new(
+                "telegram_errors_total",
+                "Failed requests to the Telegram API",
+            )
+            .namespace(NAMESPACE),
+            &["endpoint"],
+        )?;
+        let ocr = IntCounterVec::new(
+            Opts::new(
+                "ocr_total",
+                "Images read with OCR by whether the text changed the verdict",
+            )
+            .namespace(NAMESPACE),
+            &["outcome"],
+        )?;
+        let embedding_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "embedding_seconds",
+                "Time spent creating embeddings with the model",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        let handling_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "handling_seconds",
+                "Time from receiving an update to finishing processing it",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        registry.register(Box::new(updates_received.clone()))?;
+        registry.register(Box::new(queue_depth.clone()))?;
+        registry.register(Box::new(messages_classified.clone()))?;
+        registry.register(Box::new(verdicts.clone()))?;
+        registry.register(Box::new(actions.clone()))?;
+        registry.register(Box::new(telegram_errors.clone()))?;
+        registry.register(Box::new(ocr.clone()))?;
+        registry.register(Box::new(embedding_seconds.clone()))?;
+        registry.register(Box::new(handling_seconds.clone()))?;
+        Ok(Self {
+            registry,
+            updates_received,
+            queue_depth,
+            messages_classified,
+            verdicts,
+            actions,
+            telegram_errors,
+            ocr,
+            embedding_seconds,
+            handling_seconds,
+        })
+    }
+
+    // in the Prometheus text exposition format
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        metrics().verdicts.with_label_values(&["regex_pass"]).inc();
+        metrics()
+            .telegram_errors
+            .with_label_values(&["banChatMember"])
+            .inc();
+        let got = metrics().render().unwrap();
+        assert!(got.contains("airnope_verdicts_total{stage=\"regex_pass\"}"));
+        assert!(got.contains("airnope_telegram_errors_total{endpoint=\"banChatMember\"}"));
+        assert!(got.contains("# TYPE airnope_handling_seconds histogram"));
+    }
+}
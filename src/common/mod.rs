@@ -1,4 +1,10 @@
 pub mod embeddings;
+pub mod fingerprint;
+pub mod images;
+pub mod metrics;
+pub mod ocr;
 pub mod re;
 pub mod telegram;
+pub mod urls;
+pub mod wallets;
 pub mod zsc;
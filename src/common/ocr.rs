@@ -0,0 +1,104 @@
+use anyhow::Result;
+use image::DynamicImage;
+use std::env;
+
+const DETECTION_MODEL: &str = "AIRNOPE_OCR_DETECTION_MODEL";
+const RECOGNITION_MODEL: &str = "AIRNOPE_OCR_RECOGNITION_MODEL";
+
+#[cfg(feature = "ocr")]
+mod engine {
+    use anyhow::Result;
+    use image::{imageops::FilterType, DynamicImage};
+    use ocrs::{ImageSource, OcrEngine, OcrEngineParams};
+    use rten::Model;
+
+    // larger images are scaled down before reading them, which is what takes most of the time
+    const MAX_DIMENSION: u32 = 1_600;
+
+    pub struct Ocr {
+        engine: OcrEngine,
+    }
+
+    impl Ocr {
+        pub fn load(detection: &str, recognition: &str) -> Result<Self> {
+            let engine = OcrEngine::new(OcrEngineParams {
+                detection_model: Some(Model::load_file(detection)?),
+                recognition_model: Some(Model::load_file(recognition)?),
+                ..Default::default()
+            })?;
+            Ok(Self { engine })
+        }
+
+        pub fn text(&self, image: &DynamicImage) -> Result<String> {
+            let rgb = if image.width().max(image.height()) > MAX_DIMENSION {
+                image
+                    .resize(MAX_DIMENSION, MAX_DIMENSION, FilterType::Triangle)
+                    .to_rgb8()
+            } else {
+                image.to_rgb8()
+            };
+            let source = ImageSource::from_bytes(rgb.as_raw(), rgb.dimensions())?;
+            let input = self.engine.prepare_input(source)?;
+            self.engine.get_text(&input)
+        }
+    }
+}
+
+// without the `ocr` feature there is nothing to read images with, so there is no value of this type
+#[cfg(not(feature = "ocr"))]
+mod engine {
+    use anyhow::{anyhow, Result};
+    use image::DynamicImage;
+
+    pub enum Ocr {}
+
+    impl Ocr {
+        pub fn load(_detection: &str, _recognition: &str) -> Result<Self> {
+            Err(anyhow!(
+                "AirNope was built without OCR, build it with `--features ocr` to use it"
+            ))
+        }
+
+        pub fn text(&self, _image: &DynamicImage) -> Result<String> {
+            match *self {}
+        }
+    }
+}
+
+// reads text in images with models running locally on the CPU, so images are never sent anywhere
+pub use engine::Ocr;
+
+impl Ocr {
+    // OCR is enabled only when the paths to both models are set in the environment
+    pub fn from_env() -> Result<Option<Self>> {
+        let (Ok(detection), Ok(recognition)) =
+            (env::var(DETECTION_MODEL), env::var(RECOGNITION_MODEL))
+        else {
+            return Ok(None);
+        };
+        let ocr = Self::load(&detection, &recognition)?;
+        log::info!("Reading text in images with models from {detection} and {recognition}");
+        Ok(Some(ocr))
+    }
+
+    // the lines of text found in the image, ignoring blank ones
+    pub fn lines(&self, image: &DynamicImage) -> Result<Vec<String>> {
+        Ok(self
+            .text(image)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_fails_without_models() {
+        assert!(Ocr::load("missing-detection.rten", "missing-recognition.rten").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{truncated, Guess};
+use crate::{truncated, wallets, Guess};
 use anyhow::Result;
 use regex::{Regex, RegexBuilder};
 
@@ -196,9 +196,63 @@ impl RegularExpression {
         })
     }
 
+    fn keywords(&self, txt: &str) -> Vec<String> {
+        [
+            ("airdrop", &self.airdrop),
+            ("bitcoin", &self.bitcoin),
+            ("btc", &self.btc),
+            ("altcoin", &self.altcoin),
+            ("crypto", &self.crypto),
+            ("https", &self.https),
+            ("nft", &self.nft),
+            ("safeguard", &self.safeguard),
+            ("somnia", &self.somnia),
+            ("cryptocurrenc", &self.cryptocurrenc),
+            ("wallet", &self.wallet),
+            ("token", &self.token),
+            ("claim", &self.claim),
+            ("swap", &self.swap),
+            ("reward", &self.reward),
+            ("earning", &self.earning),
+            ("opportunity", &self.opportunity),
+            ("finance", &self.finance),
+            ("network", &self.network),
+            ("contract", &self.contract),
+            ("fund", &self.fund),
+            ("transaction", &self.transaction),
+            ("trading", &self.trading),
+            ("trade", &self.trade),
+            ("platform", &self.platform),
+            ("drop", &self.drop),
+            ("gana", &self.gana),
+            ("inverti", &self.inverti),
+            ("fondo", &self.fondo),
+            ("cuenta", &self.cuenta),
+            ("clic", &self.clic),
+            ("aqui", &self.aqui),
+            ("plataforma", &self.plataforma),
+            ("distribuicao", &self.distribuicao),
+            ("paga", &self.paga),
+            ("plattform", &self.plattform),
+            ("gewinne", &self.gewinne),
+            ("eingezahlt", &self.eingezahlt),
+            ("erhalten", &self.erhalten),
+            ("investieren", &self.investieren),
+            ("auszahlung", &self.auszahlung),
+            ("belohn", &self.belohn),
+            ("dollar_word", &self.dollar_word),
+        ]
+        .iter()
+        .filter(|(_, regex)| regex.is_match(txt))
+        .map(|(name, _)| name.to_string())
+        .collect()
+    }
+
     pub async fn is_spam(&self, txt: &str) -> Result<Guess> {
         let cleaned = self.cleanup.replace_all(txt, " ");
-        let result = self.airdrop.is_match(&cleaned)
+        let wallets = wallets::features(txt);
+        let result = !wallets.is_empty()
+            || self.airdrop.is_match(&cleaned)
             || self.cryptocurrenc.is_match(&cleaned)
             || self.altcoin.is_match(&cleaned)
             || self.safeguard.is_match(&cleaned)
@@ -246,14 +300,18 @@ impl RegularExpression {
             || (self.drop.is_match(&cleaned)
                 && self.network.is_match(&cleaned)
                 && self.claim.is_match(&cleaned));
+        let mut rules = vec![];
         if result {
             log::info!("Message detected as spam by RegularExpression");
             log::debug!("{}", truncated(txt));
+            rules = self.keywords(&cleaned);
+            rules.extend(wallets.iter().map(|feature| feature.to_string()));
         }
         Ok(Guess {
             is_spam: result,
             score: None,
             scores: vec![],
+            rules,
         })
     }
 }
@@ -331,10 +389,26 @@ mod tests {
                     expected, w, got.is_spam
                 );
                 assert_eq!(got.score, None);
+                assert_eq!(got.rules.is_empty(), !expected);
             }
         }
     }
 
+    #[tokio::test]
+    async fn test_is_spam_lists_matched_keywords() {
+        let model = RegularExpression::new().await.unwrap();
+        let got = model.is_spam("claim your tokens").await.unwrap();
+        assert!(got.is_spam);
+        assert_eq!(got.rules, vec!["token", "claim"]);
+
+        let got = model
+            .is_spam("send 0.1 to 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed")
+            .await
+            .unwrap();
+        assert!(got.is_spam);
+        assert_eq!(got.rules, vec!["evm_address"]);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_is_spam_with_test_data() {
         let model = RegularExpression::new().await.unwrap();
@@ -1,11 +1,25 @@
+use crate::metrics::metrics;
 use anyhow::{anyhow, Context, Result};
-use reqwest::{Client as ReqwestClient, Url};
+use moka::future::Cache;
+use reqwest::{Client as ReqwestClient, StatusCode, Url};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::{
+    collections::HashSet,
+    env, fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::sleep;
 
-const TELEGRAM_API_URL: &str = "https://api.telegram.org/bot";
+const TELEGRAM_API_URL: &str = "TELEGRAM_API_URL";
+const DEFAULT_TELEGRAM_API_URL: &str = "https://api.telegram.org";
 const DEFAULT_MAX_CONNECTIONS: u8 = 100;
+const ADMINS_CACHE_SIZE: u64 = 16_384;
+const ADMINS_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
 const DEFAULT_REACTION: &str = "👀";
+const MAX_DELETE_MESSAGES: usize = 100;
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
 const DEFAULT_ALLOWED_UPDATES: &[&str] = &[
     "message",
     "edited_message",
@@ -13,12 +27,24 @@ const DEFAULT_ALLOWED_UPDATES: &[&str] = &[
     "edited_channel_post",
     "business_message",
     "edited_business_message",
+    "callback_query",
+    "chat_member",
+    "chat_join_request",
 ];
 
 #[derive(Serialize)]
-struct GetChatMemberPayload {
+struct GetChatAdministratorsPayload {
     chat_id: i64,
-    user_id: i64,
+}
+
+#[derive(Serialize)]
+struct GetChatPayload {
+    chat_id: i64,
+}
+
+#[derive(Serialize)]
+struct GetFilePayload {
+    file_id: String,
 }
 
 #[derive(Serialize)]
@@ -40,6 +66,94 @@ struct SetMessageReactionPayload {
 struct BanChatMemberPayload {
     chat_id: i64,
     user_id: i64,
+    revoke_messages: bool,
+}
+
+#[derive(Serialize)]
+pub struct ChatPermissions {
+    can_send_messages: bool,
+    can_send_audios: bool,
+    can_send_documents: bool,
+    can_send_photos: bool,
+    can_send_videos: bool,
+    can_send_video_notes: bool,
+    can_send_voice_notes: bool,
+    can_send_polls: bool,
+    can_send_other_messages: bool,
+    can_add_web_page_previews: bool,
+    can_change_info: bool,
+    can_invite_users: bool,
+    can_pin_messages: bool,
+    can_manage_topics: bool,
+}
+
+impl ChatPermissions {
+    pub fn muted() -> Self {
+        Self {
+            can_send_messages: false,
+            can_send_audios: false,
+            can_send_documents: false,
+            can_send_photos: false,
+            can_send_videos: false,
+            can_send_video_notes: false,
+            can_send_voice_notes: false,
+            can_send_polls: false,
+            can_send_other_messages: false,
+            can_add_web_page_previews: false,
+            can_change_info: false,
+            can_invite_users: false,
+            can_pin_messages: false,
+            can_manage_topics: false,
+        }
+    }
+
+    pub fn text_only() -> Self {
+        Self {
+            can_send_messages: true,
+            ..Self::muted()
+        }
+    }
+
+    pub fn unrestricted() -> Self {
+        Self {
+            can_send_messages: true,
+            can_send_audios: true,
+            can_send_documents: true,
+            can_send_photos: true,
+            can_send_videos: true,
+            can_send_video_notes: true,
+            can_send_voice_notes: true,
+            can_send_polls: true,
+            can_send_other_messages: true,
+            can_add_web_page_previews: true,
+            can_change_info: true,
+            can_invite_users: true,
+            can_pin_messages: true,
+            can_manage_topics: true,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RestrictChatMemberPayload {
+    chat_id: i64,
+    user_id: i64,
+    permissions: ChatPermissions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until_date: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ChatJoinRequestPayload {
+    chat_id: i64,
+    user_id: i64,
+}
+
+#[derive(Serialize)]
+struct UnbanChatMemberPayload {
+    chat_id: i64,
+    user_id: i64,
+    only_if_banned: bool,
 }
 
 #[derive(Serialize)]
@@ -48,6 +162,50 @@ struct DeleteMessagePayload {
     message_id: i64,
 }
 
+#[derive(Serialize)]
+struct DeleteMessagesPayload {
+    chat_id: i64,
+    message_ids: Vec<i64>,
+}
+
+#[derive(Serialize)]
+struct LinkPreviewOptions {
+    is_disabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    pub callback_data: String,
+}
+
+#[derive(Serialize)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+#[derive(Serialize)]
+struct SendMessagePayload {
+    chat_id: i64,
+    text: String,
+    link_preview_options: LinkPreviewOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+#[derive(Serialize)]
+struct AnswerCallbackQueryPayload {
+    callback_query_id: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct ForwardMessagePayload {
+    chat_id: i64,
+    from_chat_id: i64,
+    message_id: i64,
+}
+
 #[derive(Serialize)]
 pub struct SetWebhookPayload {
     url: String,
@@ -64,23 +222,72 @@ pub struct DeleteWebhookPayload {
 #[derive(Serialize)]
 #[serde(untagged)]
 enum Payload {
-    GetChatMember(GetChatMemberPayload),
+    GetChat(GetChatPayload),
+    GetFile(GetFilePayload),
+    GetChatAdministrators(GetChatAdministratorsPayload),
     SetMessageReaction(SetMessageReactionPayload),
     BanChatMember(BanChatMemberPayload),
+    UnbanChatMember(UnbanChatMemberPayload),
+    ApproveChatJoinRequest(ChatJoinRequestPayload),
+    DeclineChatJoinRequest(ChatJoinRequestPayload),
+    RestrictChatMember(RestrictChatMemberPayload),
     DeleteMessage(DeleteMessagePayload),
+    DeleteMessages(DeleteMessagesPayload),
+    SendMessage(SendMessagePayload),
+    ForwardMessage(ForwardMessagePayload),
+    AnswerCallbackQuery(AnswerCallbackQueryPayload),
     SetWebhook(SetWebhookPayload),
     DeleteWebhook(DeleteWebhookPayload),
 }
 
+#[derive(Deserialize)]
+struct UserResponse {
+    id: i64,
+}
+
 #[derive(Deserialize)]
 struct ChatMemberResponse {
-    status: String,
+    user: UserResponse,
+}
+
+#[derive(Deserialize)]
+struct GetChatAdministratorsResponse {
+    ok: bool,
+    result: Vec<ChatMemberResponse>,
+}
+
+#[derive(Deserialize)]
+struct ChatFullInfo {
+    bio: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GetChatResponse {
+    ok: bool,
+    result: ChatFullInfo,
+}
+
+#[derive(Deserialize)]
+struct File {
+    file_size: Option<u64>,
+    file_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GetFileResponse {
+    ok: bool,
+    result: File,
 }
 
 #[derive(Deserialize)]
-struct GetChatMemberResponse {
+struct SentMessage {
+    message_id: i64,
+}
+
+#[derive(Deserialize)]
+struct SentMessageResponse {
     ok: bool,
-    result: ChatMemberResponse,
+    result: SentMessage,
 }
 
 #[derive(Deserialize)]
@@ -90,41 +297,127 @@ struct SuccessResponse {
 }
 
 enum Response {
-    ChatMember(GetChatMemberResponse),
+    Chat(GetChatResponse),
+    File(GetFileResponse),
+    ChatAdministrators(GetChatAdministratorsResponse),
+    SentMessage(SentMessageResponse),
     Success(SuccessResponse),
 }
 
+#[derive(Debug)]
+pub struct ApiError {
+    pub endpoint: String,
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl ApiError {
+    pub fn is_permanent(&self) -> bool {
+        self.status.is_client_error() && self.status != StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Request to {} failed: [{}] {}",
+            self.endpoint, self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<reqwest::Error>()
+            || cause
+                .downcast_ref::<ApiError>()
+                .is_some_and(|e| !e.is_permanent())
+    })
+}
+
+#[derive(Clone)]
 pub struct Client {
+    api_url: String,
     token: String,
     http: ReqwestClient,
+    admins: Cache<i64, Arc<HashSet<i64>>>,
 }
 
 impl Client {
     pub fn new() -> Result<Self> {
         let token = env::var("TELEGRAM_BOT_TOKEN")
             .map_err(|_| anyhow!("Environment variable TELEGRAM_BOT_TOKEN not found."))?;
+        let api_url = env::var(TELEGRAM_API_URL).unwrap_or(DEFAULT_TELEGRAM_API_URL.to_string());
+        Ok(Self::with_api_url(token, api_url))
+    }
+
+    fn with_api_url(token: String, api_url: String) -> Self {
         let http = ReqwestClient::new();
-        Ok(Client { token, http })
+        let admins = Cache::builder()
+            .max_capacity(ADMINS_CACHE_SIZE)
+            .time_to_live(ADMINS_CACHE_TTL)
+            .build();
+        Client {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+            http,
+            admins,
+        }
     }
 
     fn endpoint(&self, payload: &Payload) -> &str {
         match payload {
-            Payload::GetChatMember(_) => "getChatMember",
+            Payload::GetChat(_) => "getChat",
+            Payload::GetFile(_) => "getFile",
+            Payload::GetChatAdministrators(_) => "getChatAdministrators",
             Payload::SetMessageReaction(_) => "setMessageReaction",
             Payload::BanChatMember(_) => "banChatMember",
+            Payload::UnbanChatMember(_) => "unbanChatMember",
+            Payload::ApproveChatJoinRequest(_) => "approveChatJoinRequest",
+            Payload::DeclineChatJoinRequest(_) => "declineChatJoinRequest",
+            Payload::RestrictChatMember(_) => "restrictChatMember",
             Payload::DeleteMessage(_) => "deleteMessage",
+            Payload::DeleteMessages(_) => "deleteMessages",
+            Payload::SendMessage(_) => "sendMessage",
+            Payload::ForwardMessage(_) => "forwardMessage",
+            Payload::AnswerCallbackQuery(_) => "answerCallbackQuery",
             Payload::SetWebhook(_) => "setWebhook",
             Payload::DeleteWebhook(_) => "deleteWebhook",
         }
     }
 
     fn url(&self, endpoint: &str) -> Result<Url> {
-        let url = format!("{TELEGRAM_API_URL}{}/{}", self.token, endpoint);
+        let url = format!("{}/bot{}/{}", self.api_url, self.token, endpoint);
         Url::parse(&url).context(format!("Failed to build URL for {endpoint}"))
     }
 
     async fn post(&self, payload: &Payload) -> Result<Response> {
         let endpoint = self.endpoint(payload);
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            let error = match self.request(endpoint, payload).await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            metrics()
+                .telegram_errors
+                .with_label_values(&[endpoint])
+                .inc();
+            if attempt >= MAX_ATTEMPTS || !is_retryable(&error) {
+                return Err(error);
+            }
+            log::warn!("{error:#} (retrying in {delay:?})");
+            sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+
+    async fn request(&self, endpoint: &str, payload: &Payload) -> Result<Response> {
         let url = self
             .url(endpoint)
             .context(format!("Error creating URL for {endpoint}"))?;
@@ -152,14 +445,37 @@ impl Client {
                     result: true,
                 }));
             }
-            return Err(anyhow!("Request to {endpoint} failed: [{status}] {body}",));
+            return Err(ApiError {
+                endpoint: endpoint.to_string(),
+                status,
+                body,
+            }
+            .into());
         }
         match payload {
-            Payload::GetChatMember(_) => {
-                let chat_member: GetChatMemberResponse = serde_json::from_str(&body).context(
+            Payload::GetChat(_) => {
+                let chat: GetChatResponse = serde_json::from_str(&body).context(format!(
+                    "Failed to deserialize response from {endpoint}: {body}"
+                ))?;
+                Ok(Response::Chat(chat))
+            }
+            Payload::GetFile(_) => {
+                let file: GetFileResponse = serde_json::from_str(&body).context(format!(
+                    "Failed to deserialize response from {endpoint}: {body}"
+                ))?;
+                Ok(Response::File(file))
+            }
+            Payload::GetChatAdministrators(_) => {
+                let admins: GetChatAdministratorsResponse = serde_json::from_str(&body).context(
                     format!("Failed to deserialize response from {endpoint}: {body}"),
                 )?;
-                Ok(Response::ChatMember(chat_member))
+                Ok(Response::ChatAdministrators(admins))
+            }
+            Payload::SendMessage(_) | Payload::ForwardMessage(_) => {
+                let sent: SentMessageResponse = serde_json::from_str(&body).context(format!(
+                    "Failed to deserialize response from {endpoint}: {body}"
+                ))?;
+                Ok(Response::SentMessage(sent))
             }
             _ => {
                 let success: SuccessResponse = serde_json::from_str(&body).context(format!(
@@ -170,16 +486,79 @@ impl Client {
         }
     }
 
-    pub async fn is_admin(&self, chat_id: i64, user_id: i64) -> Result<bool> {
-        let payload = Payload::GetChatMember(GetChatMemberPayload { chat_id, user_id });
+    async fn chat_administrators(&self, chat_id: i64) -> Result<Arc<HashSet<i64>>> {
+        let payload = Payload::GetChatAdministrators(GetChatAdministratorsPayload { chat_id });
         match self.post(&payload).await? {
-            Response::ChatMember(response) => Ok(response.ok
-                && (response.result.status == "administrator"
-                    || response.result.status == "creator")),
-            _ => Err(anyhow!("Unexpected result response for getChatMember")),
+            Response::ChatAdministrators(response) if response.ok => Ok(Arc::new(
+                response
+                    .result
+                    .iter()
+                    .map(|member| member.user.id)
+                    .collect(),
+            )),
+            _ => Err(anyhow!(
+                "Unexpected result response for getChatAdministrators"
+            )),
         }
     }
 
+    pub async fn bio(&self, user_id: i64) -> Result<Option<String>> {
+        let payload = Payload::GetChat(GetChatPayload { chat_id: user_id });
+        match self.post(&payload).await? {
+            Response::Chat(response) if response.ok => Ok(response.result.bio),
+            _ => Err(anyhow!("Unexpected result response for getChat")),
+        }
+    }
+
+    pub async fn download(&self, file_id: &str, max_bytes: u64) -> Result<Vec<u8>> {
+        let payload = Payload::GetFile(GetFilePayload {
+            file_id: file_id.to_string(),
+        });
+        let file = match self.post(&payload).await? {
+            Response::File(response) if response.ok => response.result,
+            _ => return Err(anyhow!("Unexpected result response for getFile")),
+        };
+        if file.file_size.is_some_and(|size| size > max_bytes) {
+            return Err(anyhow!("File {file_id} is larger than {max_bytes} bytes"));
+        }
+        let path = file
+            .file_path
+            .ok_or(anyhow!("File {file_id} is not available for download"))?;
+        let url = format!("{}/file/bot{}/{}", self.api_url, self.token, path);
+        let mut response = self
+            .http
+            .get(Url::parse(&url).context("Failed to build URL to download file")?)
+            .send()
+            .await
+            .context(format!("Error downloading file {file_id}"))?
+            .error_for_status()?;
+        let mut contents = vec![];
+        while let Some(chunk) = response.chunk().await? {
+            contents.extend_from_slice(&chunk);
+            if contents.len() as u64 > max_bytes {
+                return Err(anyhow!("File {file_id} is larger than {max_bytes} bytes"));
+            }
+        }
+        Ok(contents)
+    }
+
+    pub async fn is_admin(&self, chat_id: i64, user_id: i64) -> Result<bool> {
+        if chat_id == user_id {
+            return Ok(false); // private chats have no admins
+        }
+        if let Some(admins) = self.admins.get(&chat_id).await {
+            return Ok(admins.contains(&user_id));
+        }
+        let admins = self.chat_administrators(chat_id).await?;
+        self.admins.insert(chat_id, admins.clone()).await;
+        Ok(admins.contains(&user_id))
+    }
+
+    pub async fn invalidate_admins(&self, chat_id: i64) {
+        log::debug!("Invalidating cached admins of chat {chat_id}");
+        self.admins.invalidate(&chat_id).await;
+    }
+
     pub async fn set_message_reaction(&self, chat_id: i64, message_id: i64) -> Result<bool> {
         let payload = Payload::SetMessageReaction(SetMessageReactionPayload {
             chat_id,
@@ -196,8 +575,17 @@ impl Client {
         }
     }
 
-    pub async fn ban_chat_member(&self, chat_id: i64, user_id: i64) -> Result<bool> {
-        let payload = Payload::BanChatMember(BanChatMemberPayload { chat_id, user_id });
+    pub async fn ban_chat_member(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        revoke_messages: bool,
+    ) -> Result<bool> {
+        let payload = Payload::BanChatMember(BanChatMemberPayload {
+            chat_id,
+            user_id,
+            revoke_messages,
+        });
         match self.post(&payload).await? {
             Response::Success(response) => Ok(response.ok && response.result),
             _ => Err(anyhow!("Unexpected result response for banChatMember")),
@@ -215,6 +603,128 @@ impl Client {
         }
     }
 
+    pub async fn delete_messages(&self, chat_id: i64, message_ids: &[i64]) -> Result<bool> {
+        let mut deleted = true;
+        for chunk in message_ids.chunks(MAX_DELETE_MESSAGES) {
+            let payload = Payload::DeleteMessages(DeleteMessagesPayload {
+                chat_id,
+                message_ids: chunk.to_vec(),
+            });
+            match self.post(&payload).await? {
+                Response::Success(response) => deleted &= response.ok && response.result,
+                _ => return Err(anyhow!("Unexpected result response for deleteMessages")),
+            }
+        }
+        Ok(deleted)
+    }
+
+    pub async fn unban_chat_member(&self, chat_id: i64, user_id: i64) -> Result<bool> {
+        let payload = Payload::UnbanChatMember(UnbanChatMemberPayload {
+            chat_id,
+            user_id,
+            only_if_banned: true,
+        });
+        match self.post(&payload).await? {
+            Response::Success(response) => Ok(response.ok && response.result),
+            _ => Err(anyhow!("Unexpected result response for unbanChatMember")),
+        }
+    }
+
+    pub async fn approve_chat_join_request(&self, chat_id: i64, user_id: i64) -> Result<bool> {
+        let payload = Payload::ApproveChatJoinRequest(ChatJoinRequestPayload { chat_id, user_id });
+        match self.post(&payload).await? {
+            Response::Success(response) => Ok(response.ok && response.result),
+            _ => Err(anyhow!(
+                "Unexpected result response for approveChatJoinRequest"
+            )),
+        }
+    }
+
+    pub async fn decline_chat_join_request(&self, chat_id: i64, user_id: i64) -> Result<bool> {
+        let payload = Payload::DeclineChatJoinRequest(ChatJoinRequestPayload { chat_id, user_id });
+        match self.post(&payload).await? {
+            Response::Success(response) => Ok(response.ok && response.result),
+            _ => Err(anyhow!(
+                "Unexpected result response for declineChatJoinRequest"
+            )),
+        }
+    }
+
+    pub async fn restrict_chat_member(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        permissions: ChatPermissions,
+        until: Option<Duration>,
+    ) -> Result<bool> {
+        let until_date = match until {
+            Some(duration) => Some(
+                (SystemTime::now() + duration)
+                    .duration_since(UNIX_EPOCH)?
+                    .as_secs(),
+            ),
+            None => None,
+        };
+        let payload = Payload::RestrictChatMember(RestrictChatMemberPayload {
+            chat_id,
+            user_id,
+            permissions,
+            until_date,
+        });
+        match self.post(&payload).await? {
+            Response::Success(response) => Ok(response.ok && response.result),
+            _ => Err(anyhow!("Unexpected result response for restrictChatMember")),
+        }
+    }
+
+    pub async fn send_message(
+        &self,
+        chat_id: i64,
+        text: &str,
+        reply_markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<i64> {
+        let payload = Payload::SendMessage(SendMessagePayload {
+            chat_id,
+            text: text.to_string(),
+            link_preview_options: LinkPreviewOptions { is_disabled: true },
+            reply_markup,
+        });
+        match self.post(&payload).await? {
+            Response::SentMessage(response) if response.ok => Ok(response.result.message_id),
+            _ => Err(anyhow!("Unexpected result response for sendMessage")),
+        }
+    }
+
+    pub async fn forward_message(
+        &self,
+        chat_id: i64,
+        from_chat_id: i64,
+        message_id: i64,
+    ) -> Result<i64> {
+        let payload = Payload::ForwardMessage(ForwardMessagePayload {
+            chat_id,
+            from_chat_id,
+            message_id,
+        });
+        match self.post(&payload).await? {
+            Response::SentMessage(response) if response.ok => Ok(response.result.message_id),
+            _ => Err(anyhow!("Unexpected result response for forwardMessage")),
+        }
+    }
+
+    pub async fn answer_callback_query(&self, callback_query_id: &str, text: &str) -> Result<bool> {
+        let payload = Payload::AnswerCallbackQuery(AnswerCallbackQueryPayload {
+            callback_query_id: callback_query_id.to_string(),
+            text: text.to_string(),
+        });
+        match self.post(&payload).await? {
+            Response::Success(response) => Ok(response.ok && response.result),
+            _ => Err(anyhow!(
+                "Unexpected result response for answerCallbackQuery"
+            )),
+        }
+    }
+
     pub async fn set_webhook(&self, secret_token: &str) -> Result<bool> {
         let url = env::var("TELEGRAM_WEBHOOK_URL")
             .map_err(|_| anyhow!("Environment variable TELEGRAM_WEBHOOK_URL not found."))?;
@@ -246,3 +756,163 @@ impl Client {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const FILE: &[u8] = b"not really a photo";
+
+    static DELETE_MESSAGE_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static BAN_CHAT_MEMBER_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static GET_CHAT_ADMINISTRATORS_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    async fn delete_message() -> HttpResponse {
+        if DELETE_MESSAGE_CALLS.fetch_add(1, Ordering::SeqCst) == 0 {
+            return HttpResponse::InternalServerError().finish();
+        }
+        HttpResponse::Ok().json(serde_json::json!({"ok": true, "result": true}))
+    }
+
+    async fn ban_chat_member() -> HttpResponse {
+        BAN_CHAT_MEMBER_CALLS.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: not enough rights to restrict/unrestrict chat member"
+        }))
+    }
+
+    async fn get_chat_administrators() -> HttpResponse {
+        GET_CHAT_ADMINISTRATORS_CALLS.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Ok().json(serde_json::json!({
+            "ok": true,
+            "result": [{"status": "creator", "user": {"id": 42}}]
+        }))
+    }
+
+    async fn get_file() -> HttpResponse {
+        HttpResponse::Ok().json(serde_json::json!({
+            "ok": true,
+            "result": {
+                "file_id": "AgACAgEAAxkBAAIBZ2aQ1",
+                "file_unique_id": "AQADq7AxG8Q",
+                "file_size": FILE.len(),
+                "file_path": "photos/file_42.jpg"
+            }
+        }))
+    }
+
+    async fn file() -> HttpResponse {
+        HttpResponse::Ok().body(FILE)
+    }
+
+    fn stub() -> Client {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/bot42:token/getFile", web::post().to(get_file))
+                .route("/bot42:token/deleteMessage", web::post().to(delete_message))
+                .route(
+                    "/bot42:token/getChatAdministrators",
+                    web::post().to(get_chat_administrators),
+                )
+                .route(
+                    "/bot42:token/banChatMember",
+                    web::post().to(ban_chat_member),
+                )
+                .route("/file/bot42:token/photos/file_42.jpg", web::get().to(file))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        tokio::spawn(server.run());
+        Client::with_api_url("42:token".to_string(), format!("http://{address}/"))
+    }
+
+    #[test]
+    fn test_chat_permissions() {
+        let fields = [
+            "can_send_messages",
+            "can_send_audios",
+            "can_send_documents",
+            "can_send_photos",
+            "can_send_videos",
+            "can_send_video_notes",
+            "can_send_voice_notes",
+            "can_send_polls",
+            "can_send_other_messages",
+            "can_add_web_page_previews",
+            "can_change_info",
+            "can_invite_users",
+            "can_pin_messages",
+            "can_manage_topics",
+        ];
+        for (permissions, allowed) in [
+            (ChatPermissions::muted(), 0),
+            (ChatPermissions::text_only(), 1),
+            (ChatPermissions::unrestricted(), fields.len()),
+        ] {
+            let value = serde_json::to_value(permissions).unwrap();
+            let value = value.as_object().unwrap();
+            assert_eq!(value.len(), fields.len());
+            for field in fields {
+                assert!(value[field].is_boolean(), "{field} is missing");
+            }
+            let got = value.values().filter(|v| v.as_bool().unwrap()).count();
+            assert_eq!(got, allowed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let client = stub();
+        let got = client
+            .download("AgACAgEAAxkBAAIBZ2aQ1", 1024)
+            .await
+            .unwrap();
+        assert_eq!(got, FILE);
+        assert!(client.download("AgACAgEAAxkBAAIBZ2aQ1", 8).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retries_only_transient_errors() {
+        let client = stub();
+        assert!(client.delete_message(-42, 1).await.unwrap());
+        assert_eq!(DELETE_MESSAGE_CALLS.load(Ordering::SeqCst), 2);
+        assert!(client.ban_chat_member(-42, 42, false).await.is_err());
+        assert_eq!(BAN_CHAT_MEMBER_CALLS.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_is_admin_is_cached() {
+        let mut client = stub();
+        client.admins = Cache::builder()
+            .time_to_live(Duration::from_millis(200))
+            .build();
+        assert!(client.is_admin(-42, 42).await.unwrap());
+        assert!(!client.is_admin(-42, 4242).await.unwrap());
+        assert_eq!(GET_CHAT_ADMINISTRATORS_CALLS.load(Ordering::SeqCst), 1);
+        sleep(Duration::from_millis(300)).await;
+        assert!(client.is_admin(-42, 42).await.unwrap());
+        assert_eq!(GET_CHAT_ADMINISTRATORS_CALLS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let error = |status| {
+            anyhow::Error::new(ApiError {
+                endpoint: "banChatMember".to_string(),
+                status,
+                body: "".to_string(),
+            })
+            .context("Error marking message as spam")
+        };
+        assert!(is_retryable(&error(StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(is_retryable(&error(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_retryable(&error(StatusCode::BAD_REQUEST)));
+        assert!(!is_retryable(&anyhow!("Error loading chat settings")));
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use reqwest::Url;
+use std::{env, fmt, sync::LazyLock};
+use tokio::{fs, sync::OnceCell};
+
+const BLOCKLIST_PATH: &str = "AIRNOPE_BLOCKLIST_PATH";
+const ALLOWLIST_PATH: &str = "AIRNOPE_ALLOWLIST_PATH";
+const TELEGRAM_HOSTS: [&str; 3] = ["t.me", "telegram.me", "telegram.dog"];
+const SHORTENERS: [&str; 16] = [
+    "bit.ly",
+    "cutt.ly",
+    "goo.gl",
+    "is.gd",
+    "ow.ly",
+    "rb.gy",
+    "rebrand.ly",
+    "s.id",
+    "shorturl.at",
+    "t.co",
+    "t.ly",
+    "tiny.cc",
+    "tinyurl.com",
+    "u.to",
+    "v.gd",
+    "x.gd",
+];
+
+pub(crate) static LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)(?:https?://)?(?:[\p{L}\p{N}-]+\.)+\p{L}{2,}(?:/[^\s<>"']*)?"#).unwrap()
+});
+static MENTION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|[^\w@])@(\w{4,32})").unwrap());
+static REPUTATION: OnceCell<Reputation> = OnceCell::const_new();
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Link {
+    Domain(String),
+    Username(String),
+    Invite(String),
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Link::Domain(host) => write!(f, "{host}"),
+            Link::Username(username) => write!(f, "@{username}"),
+            Link::Invite(hash) => write!(f, "t.me/+{hash}"),
+        }
+    }
+}
+
+impl Link {
+    // hosts are lowercased and converted to punycode, so look-alike Unicode domains are compared
+    // by what they really are
+    fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+        let url = if raw.contains("://") {
+            Url::parse(raw)
+        } else {
+            Url::parse(&format!("https://{raw}"))
+        }
+        .ok()?;
+        let host = url.host_str()?.trim_end_matches('.');
+        let host = host.strip_prefix("www.").unwrap_or(host).to_string();
+        if !TELEGRAM_HOSTS.contains(&host.as_str()) {
+            return Some(Link::Domain(host));
+        }
+        let mut path = url.path_segments()?;
+        let first = path.next().filter(|segment| !segment.is_empty())?;
+        if let Some(hash) = first.strip_prefix('+') {
+            return Some(Link::Invite(hash.to_string()));
+        }
+        if first == "joinchat" {
+            return path.next().map(|hash| Link::Invite(hash.to_string()));
+        }
+        Some(Link::Username(first.to_lowercase()))
+    }
+
+    pub fn is_shortener(&self) -> bool {
+        matches!(self, Link::Domain(host) if SHORTENERS.contains(&host.as_str()))
+    }
+}
+
+// links, Telegram links and usernames mentioned in the text, without duplicates
+pub fn extract(txt: &str) -> Vec<Link> {
+    extract_with_entities(txt, &[])
+}
+
+// entities are the URLs and `@usernames` Telegram found in the message, parsed as they are
+// because the regular expression might not recognise them (e.g. IP addresses)
+fn extract_with_entities(txt: &str, entities: &[String]) -> Vec<Link> {
+    let entities = entities
+        .iter()
+        .filter_map(|entity| match entity.strip_prefix('@') {
+            Some(username) => Some(Link::Username(username.to_lowercase())),
+            None => Link::parse(entity),
+        });
+    let links = LINK.find_iter(txt).filter_map(|m| Link::parse(m.as_str()));
+    let mentions = MENTION
+        .captures_iter(txt)
+        .map(|c| Link::Username(c[1].to_lowercase()));
+    let mut result: Vec<Link> = vec![];
+    for link in entities.chain(links).chain(mentions) {
+        if !result.contains(&link) {
+            result.push(link);
+        }
+    }
+    result
+}
+
+#[derive(Debug, PartialEq)]
+enum Rule {
+    Exact(Link),
+    Subdomains(String),
+}
+
+impl Rule {
+    // one rule per line: `example.com`, `*.example.com` (the domain and all its subdomains),
+    // `@username` or `t.me/+invite`
+    fn parse(line: &str) -> Option<Result<Self>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        if let Some(domain) = line.strip_prefix("*.") {
+            return Some(match Link::parse(domain) {
+                Some(Link::Domain(host)) => Ok(Rule::Subdomains(host)),
+                _ => Err(anyhow!("Invalid domain in rule: {line}")),
+            });
+        }
+        let link = match line.strip_prefix('@') {
+            Some(username) => Some(Link::Username(username.to_lowercase())),
+            None => Link::parse(line),
+        };
+        Some(link.map(Rule::Exact).ok_or(anyhow!("Invalid rule: {line}")))
+    }
+
+    fn matches(&self, link: &Link) -> bool {
+        match (self, link) {
+            (Rule::Exact(rule), link) => rule == link,
+            (Rule::Subdomains(domain), Link::Domain(host)) => {
+                host == domain || host.ends_with(&format!(".{domain}"))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn rules(contents: &str) -> Result<Vec<Rule>> {
+    contents.lines().filter_map(Rule::parse).collect()
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Verdict {
+    pub blocked: Vec<String>,
+    pub suspicious: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Reputation {
+    block: Vec<Rule>,
+    allow: Vec<Rule>,
+}
+
+impl Reputation {
+    pub fn from_lists(block: &str, allow: &str) -> Result<Self> {
+        Ok(Self {
+            block: rules(block)?,
+            allow: rules(allow)?,
+        })
+    }
+
+    async fn from_env() -> Result<Self> {
+        let mut lists = vec![];
+        for name in [BLOCKLIST_PATH, ALLOWLIST_PATH] {
+            let contents = match env::var(name) {
+                Ok(path) => {
+                    let contents = fs::read_to_string(&path).await?;
+                    log::info!("Using {name} from {path}");
+                    contents
+                }
+                Err(_) => String::new(),
+            };
+            lists.push(contents);
+        }
+        Self::from_lists(&lists[0], &lists[1])
+    }
+
+    // loaded once from the paths set in the environment
+    pub async fn shared() -> Result<&'static Self> {
+        REPUTATION.get_or_try_init(Self::from_env).await
+    }
+
+    pub fn check(&self, txt: &str, entities: &[String]) -> Verdict {
+        let mut verdict = Verdict::default();
+        for link in extract_with_entities(txt, entities) {
+            if self.allow.iter().any(|rule| rule.matches(&link)) {
+                continue;
+            }
+            if self.block.iter().any(|rule| rule.matches(&link)) {
+                verdict.blocked.push(format!("blocked link {link}"));
+            } else if link.is_shortener() {
+                verdict.suspicious.push(format!("link shortener {link}"));
+            }
+        }
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let txt = "Claim at https://WWW.Claim-Reward.pages.dev/?ref=1, join t.me/+AbCd123 or \
+            https://t.me/joinchat/XyZ, follow t.me/Scam_Bot and @Support_Team, \
+            see bit.ly/3xyz or пример.рф (email me at someone@example.com)";
+        let expected = vec![
+            Link::Domain("claim-reward.pages.dev".to_string()),
+            Link::Invite("AbCd123".to_string()),
+            Link::Invite("XyZ".to_string()),
+            Link::Username("scam_bot".to_string()),
+            Link::Domain("bit.ly".to_string()),
+            Link::Domain("xn--e1afmkfd.xn--p1ai".to_string()),
+            Link::Domain("example.com".to_string()),
+            Link::Username("support_team".to_string()),
+        ];
+        assert_eq!(extract(txt), expected);
+    }
+
+    #[test]
+    fn test_rules() {
+        let got = rules("# scams\n*.pages.dev\n\n@Scam_Bot\nt.me/+AbCd123\nпример.рф").unwrap();
+        assert_eq!(
+            got,
+            vec![
+                Rule::Subdomains("pages.dev".to_string()),
+                Rule::Exact(Link::Username("scam_bot".to_string())),
+                Rule::Exact(Link::Invite("AbCd123".to_string())),
+                Rule::Exact(Link::Domain("xn--e1afmkfd.xn--p1ai".to_string())),
+            ]
+        );
+        assert!(rules("*.").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let reputation =
+            Reputation::from_lists("*.pages.dev\n@scam_bot", "docs.pages.dev").unwrap();
+        let verdict = reputation.check(
+            "Claim at claim.pages.dev, read docs.pages.dev, ask @Scam_Bot, see bit.ly/3xyz",
+            &[],
+        );
+        assert_eq!(
+            verdict,
+            Verdict {
+                blocked: vec![
+                    "blocked link claim.pages.dev".to_string(),
+                    "blocked link @scam_bot".to_string()
+                ],
+                suspicious: vec!["link shortener bit.ly".to_string()],
+            }
+        );
+        assert_eq!(
+            reputation.check("Nothing to see at github.com", &[]),
+            Verdict::default()
+        );
+    }
+
+    #[test]
+    fn test_check_entities() {
+        let reputation = Reputation::from_lists(
+            "203.0.113.7
+@scam_bot",
+            "",
+        )
+        .unwrap();
+        let verdict = reputation.check(
+            "Claim here, ask support",
+            &[
+                "http://203.0.113.7:8080/claim".to_string(),
+                "@Scam_Bot".to_string(),
+            ],
+        );
+        assert_eq!(
+            verdict.blocked,
+            vec![
+                "blocked link 203.0.113.7".to_string(),
+                "blocked link @scam_bot".to_string()
+            ]
+        );
+    }
+}
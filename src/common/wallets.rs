@@ -0,0 +1,187 @@
+use bech32::{hrp, segwit};
+use bip39::Language;
+use regex::Regex;
+use sha3::{Digest, Keccak256};
+use std::{fmt, sync::LazyLock};
+
+const MNEMONIC_MIN_WORDS: usize = 12;
+
+static CANDIDATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b[0-9A-Za-z]{26,62}\b").unwrap());
+static WORD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\p{L}+").unwrap());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Feature {
+    Evm,
+    Bitcoin,
+    Tron,
+    Solana,
+    SeedPhrase,
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Feature::Evm => write!(f, "evm_address"),
+            Feature::Bitcoin => write!(f, "bitcoin_address"),
+            Feature::Tron => write!(f, "tron_address"),
+            Feature::Solana => write!(f, "solana_address"),
+            Feature::SeedPhrase => write!(f, "seed_phrase"),
+        }
+    }
+}
+
+// EIP-55: addresses in a single case have no checksum, mixed case ones must match the hash
+fn is_evm(candidate: &str) -> bool {
+    let Some(hex) = candidate.strip_prefix("0x") else {
+        return false;
+    };
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return false;
+    }
+    if hex == hex.to_lowercase() || hex == hex.to_uppercase() {
+        return true;
+    }
+    let hash = Keccak256::digest(hex.to_lowercase().as_bytes());
+    hex.chars().enumerate().all(|(idx, c)| {
+        let nibble = (hash[idx / 2] >> (if idx % 2 == 0 { 4 } else { 0 })) & 0x0f;
+        !c.is_ascii_alphabetic() || c.is_ascii_uppercase() == (nibble >= 8)
+    })
+}
+
+fn is_base58check(candidate: &str, version: u8) -> bool {
+    bs58::decode(candidate)
+        .with_check(Some(version))
+        .into_vec()
+        .is_ok()
+}
+
+fn is_bitcoin(candidate: &str) -> bool {
+    if candidate.to_lowercase().starts_with("bc1") {
+        return segwit::decode(candidate).is_ok_and(|(prefix, _, _)| prefix == hrp::BC);
+    }
+    match candidate.chars().next() {
+        Some('1') => is_base58check(candidate, 0x00),
+        Some('3') => is_base58check(candidate, 0x05),
+        _ => false,
+    }
+}
+
+fn is_tron(candidate: &str) -> bool {
+    candidate.len() == 34 && candidate.starts_with('T') && is_base58check(candidate, 0x41)
+}
+
+// Solana addresses are public keys without a checksum, so to avoid matching long words this
+// requires the mix of digits and cases random keys always have
+fn is_solana(candidate: &str) -> bool {
+    (32..=44).contains(&candidate.len())
+        && candidate.chars().any(|c| c.is_ascii_digit())
+        && candidate.chars().any(|c| c.is_ascii_uppercase())
+        && candidate.chars().any(|c| c.is_ascii_lowercase())
+        && bs58::decode(candidate)
+            .into_vec()
+            .is_ok_and(|bytes| bytes.len() == 32)
+}
+
+// a run of words that are all in the BIP-39 English word list, long enough to be a mnemonic
+fn has_seed_phrase(txt: &str) -> bool {
+    let mut run = 0;
+    for word in WORD.find_iter(txt) {
+        if Language::English
+            .find_word(&word.as_str().to_lowercase())
+            .is_some()
+        {
+            run += 1;
+            if run >= MNEMONIC_MIN_WORDS {
+                return true;
+            }
+        } else {
+            run = 0;
+        }
+    }
+    false
+}
+
+pub fn features(txt: &str) -> Vec<Feature> {
+    let mut result = vec![];
+    for candidate in CANDIDATE.find_iter(txt).map(|m| m.as_str()) {
+        let feature = if is_evm(candidate) {
+            Some(Feature::Evm)
+        } else if is_bitcoin(candidate) {
+            Some(Feature::Bitcoin)
+        } else if is_tron(candidate) {
+            Some(Feature::Tron)
+        } else if is_solana(candidate) {
+            Some(Feature::Solana)
+        } else {
+            None
+        };
+        if let Some(feature) = feature {
+            if !result.contains(&feature) {
+                result.push(feature);
+            }
+        }
+    }
+    if has_seed_phrase(txt) {
+        result.push(Feature::SeedPhrase);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_evm() {
+        for address in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0x52908400098527886e0f7030069857d2e4169ee7",
+        ] {
+            assert!(is_evm(address), "{address}");
+        }
+        for address in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD", // wrong checksum
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA",   // too short
+        ] {
+            assert!(!is_evm(address), "{address}");
+        }
+    }
+
+    #[test]
+    fn test_features() {
+        let test_cases = vec![
+            (
+                "Send to 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed now",
+                vec![Feature::Evm],
+            ),
+            ("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", vec![Feature::Bitcoin]),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", vec![Feature::Bitcoin]),
+            (
+                "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+                vec![Feature::Bitcoin],
+            ),
+            ("TXhaWVBuHr5owL212R3cYQcZKE8wymQzMJ", vec![Feature::Tron]),
+            (
+                "So11111111111111111111111111111111111111112",
+                vec![Feature::Solana],
+            ),
+            (
+                "Import abandon abandon abandon abandon abandon abandon abandon abandon \
+                abandon abandon abandon about to claim",
+                vec![Feature::SeedPhrase],
+            ),
+            ("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb", vec![]), // wrong checksum
+            ("Pneumonoultramicroscopicsilicovolcanoconiosis", vec![]),
+            (
+                "the quick brown fox jumps over the lazy dog and then the cat",
+                vec![],
+            ),
+        ];
+        for (txt, expected) in test_cases {
+            assert_eq!(features(txt), expected, "{txt}");
+        }
+    }
+}
@@ -21,6 +21,7 @@ type LabelVectors = Vec<[f32; EMBEDDINGS_SIZE]>;
 #[derive(Clone)]
 pub struct ZeroShotClassification {
     vectors: LabelVectors,
+    threshold: f32,
 }
 
 pub fn average_without_extremes(scores: &Vec<f32>) -> f32 {
@@ -58,7 +59,15 @@ impl ZeroShotClassification {
         )
         .await?;
 
-        Ok(Self { vectors })
+        Ok(Self {
+            vectors,
+            threshold: THRESHOLD,
+        })
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
     }
 
     pub async fn default(embeddings: &Arc<Mutex<Embeddings>>) -> Result<Self> {
@@ -73,7 +82,7 @@ impl ZeroShotClassification {
             .map(|label| cosine_distance(label.to_vec(), vector.to_vec()))
             .collect::<Vec<f32>>();
         let score = average_without_extremes(&scores);
-        let result = score > THRESHOLD;
+        let result = score > self.threshold;
         if result {
             log::info!("Message detected as spam by ZeroShotClassification (score = {score})",);
             log::debug!("{}", truncated(txt));
@@ -82,6 +91,7 @@ impl ZeroShotClassification {
             is_spam: result,
             score: Some(score),
             scores,
+            rules: vec![],
         })
     }
 }
@@ -0,0 +1,75 @@
+use airnope::images::{decode, dhash, KnownImages};
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use tokio::{fs, io::AsyncWriteExt};
+
+// appends the hashes of the images to the list of known spam images, skipping the ones already
+// known (the images themselves are not stored anywhere)
+pub async fn add(paths: Vec<PathBuf>) -> Result<()> {
+    let list = KnownImages::path().ok_or(anyhow!(
+        "Set AIRNOPE_IMAGE_HASHES_PATH to the list of known spam images"
+    ))?;
+    append(&list, paths).await
+}
+
+async fn append(list: &Path, paths: Vec<PathBuf>) -> Result<()> {
+    let mut known = match fs::read_to_string(list).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(list)
+        .await?;
+    if !known.is_empty() && !known.ends_with('\n') {
+        file.write_all(b"\n").await?;
+        known.push('\n');
+    }
+    for path in paths {
+        let hash = dhash(&decode(&fs::read(&path).await?)?);
+        if KnownImages::parse(&known)?.contains(hash) {
+            log::info!("{} is already a known spam image", path.display());
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let line = format!("{hash:016x} # {name}\n");
+        file.write_all(line.as_bytes()).await?;
+        known.push_str(&line);
+        log::info!(
+            "Added {} ({hash:016x}) to {}",
+            path.display(),
+            list.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::env;
+
+    #[tokio::test]
+    async fn test_append_to_list_without_trailing_newline() {
+        let dir = env::temp_dir().join(format!("airnope-hashes-{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let list = dir.join("known.txt");
+        let banner = dir.join("banner.png");
+        fs::write(&list, "0123456789abcdef # hand edited")
+            .await
+            .unwrap();
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, 0])
+        }));
+        image.save_with_format(&banner, ImageFormat::Png).unwrap();
+        append(&list, vec![banner]).await.unwrap();
+        let contents = fs::read_to_string(&list).await.unwrap();
+        fs::remove_dir_all(&dir).await.unwrap();
+        let known = KnownImages::parse(&contents).unwrap();
+        assert!(known.contains(0x0123456789abcdef));
+        assert!(known.contains(dhash(&image)));
+    }
+}
@@ -0,0 +1,99 @@
+use moka::future::Cache;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const CAPACITY: u64 = 65_536;
+// the most messages Telegram deletes in a single request
+const MESSAGES_PER_USER: usize = 100;
+
+type Messages = Arc<Mutex<VecDeque<(Instant, i64)>>>;
+
+// keeps only the IDs of the messages each user sent recently in each chat, so they can all be
+// deleted if the user is banned for spam
+pub struct History {
+    window: Duration,
+    messages: Cache<(i64, i64), Messages>,
+}
+
+impl History {
+    pub fn new(window: Duration) -> Self {
+        let messages = Cache::builder()
+            .max_capacity(CAPACITY)
+            .time_to_idle(window)
+            .build();
+        Self { window, messages }
+    }
+
+    pub async fn record(&self, chat_id: i64, user_id: i64, message_id: i64) {
+        let messages = self
+            .messages
+            .get_with((chat_id, user_id), async { Arc::default() })
+            .await;
+        let Ok(mut messages) = messages.lock() else {
+            return;
+        };
+        while messages
+            .front()
+            .is_some_and(|(at, _)| at.elapsed() > self.window)
+            || messages.len() >= MESSAGES_PER_USER
+        {
+            messages.pop_front();
+        }
+        messages.push_back((Instant::now(), message_id));
+    }
+
+    // returns the IDs of the messages sent within the window, and forgets them
+    pub async fn take(&self, chat_id: i64, user_id: i64) -> Vec<i64> {
+        let Some(messages) = self.messages.remove(&(chat_id, user_id)).await else {
+            return vec![];
+        };
+        let Ok(messages) = messages.lock() else {
+            return vec![];
+        };
+        messages
+            .iter()
+            .filter(|(at, _)| at.elapsed() <= self.window)
+            .map(|(_, id)| *id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_take_recent_messages() {
+        let history = History::new(Duration::from_secs(60));
+        for id in 1..=3 {
+            history.record(-42, 42, id).await;
+        }
+        history.record(-1, 42, 4).await;
+        assert_eq!(history.take(-42, 42).await, vec![1, 2, 3]);
+        assert_eq!(history.take(-42, 42).await, Vec::<i64>::new());
+        assert_eq!(history.take(-1, 42).await, vec![4]);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_only_the_last_messages() {
+        let history = History::new(Duration::from_secs(60));
+        for id in 0..150 {
+            history.record(-42, 42, id).await;
+        }
+        let got = history.take(-42, 42).await;
+        assert_eq!(got.len(), MESSAGES_PER_USER);
+        assert_eq!(got.first(), Some(&50));
+    }
+
+    #[tokio::test]
+    async fn test_forgets_old_messages() {
+        let history = History::new(Duration::from_millis(50));
+        history.record(-42, 42, 1).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        history.record(-42, 42, 2).await;
+        assert_eq!(history.take(-42, 42).await, vec![2]);
+    }
+}
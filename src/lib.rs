@@ -1,11 +1,17 @@
 pub mod common;
 pub use common::embeddings;
+pub use common::fingerprint;
+pub use common::images;
+pub use common::metrics;
+pub use common::ocr;
 pub use common::re;
 pub use common::telegram;
+pub use common::urls;
+pub use common::wallets;
 pub use common::zsc;
 
 use anyhow::Result;
-use common::zsc::ZeroShotClassification;
+use common::{metrics::metrics, zsc::ZeroShotClassification};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -16,26 +22,74 @@ pub struct Guess {
     pub is_spam: bool,
     pub score: Option<f32>,
     pub scores: Vec<f32>,
+    pub rules: Vec<String>,
 }
 
 pub async fn is_spam_with_custom_classifier(
     embeddings: &Arc<Mutex<embeddings::Embeddings>>,
     classifier: ZeroShotClassification,
     txt: &str,
+    entities: &[String],
 ) -> Result<Guess> {
+    classify(embeddings, classifier, txt, entities, true).await
+}
+
+// for when there are other reasons to suspect a message, every message goes through the
+// zero-shot classifier instead of only the ones matching the regular expressions
+pub async fn is_spam_without_regex_gate(
+    embeddings: &Arc<Mutex<embeddings::Embeddings>>,
+    classifier: ZeroShotClassification,
+    txt: &str,
+    entities: &[String],
+) -> Result<Guess> {
+    classify(embeddings, classifier, txt, entities, false).await
+}
+
+async fn classify(
+    embeddings: &Arc<Mutex<embeddings::Embeddings>>,
+    classifier: ZeroShotClassification,
+    txt: &str,
+    entities: &[String],
+    gate: bool,
+) -> Result<Guess> {
+    metrics().messages_classified.inc();
+    let links = urls::Reputation::shared().await?.check(txt, entities);
+    if !links.blocked.is_empty() {
+        log::info!("Message detected as spam by its links");
+        metrics()
+            .verdicts
+            .with_label_values(&["blocked_link"])
+            .inc();
+        return Ok(Guess {
+            is_spam: true,
+            score: None,
+            scores: vec![],
+            rules: links.blocked,
+        });
+    }
     let regex = re::RegularExpression::new().await?;
     let result = regex.is_spam(txt).await?;
-    if !result.is_spam {
+    if gate && !result.is_spam && links.suspicious.is_empty() {
+        metrics().verdicts.with_label_values(&["regex_pass"]).inc();
         return Ok(result);
     }
-    classifier.is_spam(embeddings, txt).await
+    let mut guess = classifier.is_spam(embeddings, txt).await?;
+    guess.rules = result.rules;
+    guess.rules.extend(links.suspicious);
+    let stage = if guess.is_spam {
+        "zsc_spam"
+    } else {
+        "zsc_pass"
+    };
+    metrics().verdicts.with_label_values(&[stage]).inc();
+    Ok(guess)
 }
 pub async fn is_spam(embeddings: &Arc<Mutex<embeddings::Embeddings>>, txt: &str) -> Result<Guess> {
     let zero_shot = zsc::ZeroShotClassification::default(embeddings).await?;
-    is_spam_with_custom_classifier(embeddings, zero_shot, txt).await
+    is_spam_with_custom_classifier(embeddings, zero_shot, txt, &[]).await
 }
 
-fn truncated(message: &str) -> String {
+pub fn truncated(message: &str) -> String {
     let mut msg = message.to_string();
     msg.retain(|c| !c.is_control() || c == ' ');
     msg = msg.trim().to_string();
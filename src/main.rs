@@ -3,10 +3,19 @@ use clap::Parser;
 use cli::{Cli, Commands};
 use std::env;
 
+mod albums;
 mod bench;
 mod cache;
 mod cli;
+mod commands;
+mod hashes;
+mod history;
+mod modlog;
+mod newcomers;
+mod raids;
 mod repl;
+mod settings;
+mod storage;
 mod webhook;
 
 const DEFAULT_LOG_LEVEL: &str = "INFO";
@@ -37,5 +46,6 @@ async fn main() -> Result<()> {
         Commands::Download => cache::download_all().await,
         Commands::Bench { label, pattern } => bench::run(label, pattern).await,
         Commands::CleanCache { dry_run } => cache::clean_rust_bert_cache(dry_run).await,
+        Commands::AddImage { paths } => hashes::add(paths).await,
     }
 }
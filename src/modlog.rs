@@ -0,0 +1,568 @@
+use crate::storage::{AsyncStorage, Reported};
+use airnope::{
+    fingerprint::simhash,
+    telegram::{ChatPermissions, Client, InlineKeyboardButton, InlineKeyboardMarkup},
+    truncated,
+};
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::fs;
+
+const LOG_CHAT_ID: &str = "AIRNOPE_LOG_CHAT_ID";
+const LOG_FORWARD: &str = "AIRNOPE_LOG_FORWARD";
+const HAM_PATH: &str = "AIRNOPE_HAM_PATH";
+const REDACTED_LINK: &str = "[link]";
+const REDACTED_MENTION: &str = "@[user]";
+
+static LINKS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b(?:https?://|www\.|t\.me/)\S+").unwrap());
+static MENTIONS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"@\w{4,}").unwrap());
+
+fn redacted(message: &str) -> String {
+    let message = LINKS.replace_all(message, REDACTED_LINK);
+    let message = MENTIONS.replace_all(&message, REDACTED_MENTION);
+    truncated(&message)
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Ban,
+    Mute,
+    Delete,
+    React,
+    Shadow,
+    Restrict,
+    Log,
+    Decline,
+}
+
+impl Action {
+    pub fn removes_message(&self) -> bool {
+        matches!(self, Action::Ban | Action::Mute | Action::Delete)
+    }
+
+    fn description(&self) -> &str {
+        match self {
+            Action::Ban => "deleted message and banned user",
+            Action::Mute => "deleted message and muted user",
+            Action::Delete => "deleted message",
+            Action::React => "reacted (sender is an admin)",
+            Action::Shadow => "none (shadow mode)",
+            Action::Restrict => "restricted user to text until their first message is checked",
+            Action::Log => "none (logged only)",
+            Action::Decline => "declined join request",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Ban => write!(f, "ban"),
+            Action::Mute => write!(f, "mute"),
+            Action::Delete => write!(f, "delete"),
+            Action::React => write!(f, "react"),
+            Action::Shadow => write!(f, "shadow"),
+            Action::Restrict => write!(f, "restrict"),
+            Action::Log => write!(f, "log"),
+            Action::Decline => write!(f, "decline"),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    // only the actions admins can choose from
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "ban" => Ok(Action::Ban),
+            "mute" => Ok(Action::Mute),
+            "delete" => Ok(Action::Delete),
+            _ => Err(anyhow!("Unknown action {value}, use ban, mute or delete")),
+        }
+    }
+}
+
+// what to do with new members whose profile looks like spam
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinAction {
+    Ban,
+    Restrict,
+    Log,
+}
+
+impl From<JoinAction> for Action {
+    fn from(action: JoinAction) -> Self {
+        match action {
+            JoinAction::Ban => Action::Ban,
+            JoinAction::Restrict => Action::Restrict,
+            JoinAction::Log => Action::Log,
+        }
+    }
+}
+
+impl fmt::Display for JoinAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Action::from(*self).fmt(f)
+    }
+}
+
+impl FromStr for JoinAction {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "ban" => Ok(JoinAction::Ban),
+            "restrict" => Ok(JoinAction::Restrict),
+            "log" => Ok(JoinAction::Log),
+            _ => Err(anyhow!(
+                "Unknown action for new members {value}, use ban, restrict, log or off"
+            )),
+        }
+    }
+}
+
+// which messages of a user banned for spam are deleted, besides the one that was detected
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cleanup {
+    Off,
+    #[default]
+    Recent,
+    All,
+}
+
+impl fmt::Display for Cleanup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cleanup::Off => write!(f, "off"),
+            Cleanup::Recent => write!(f, "recent"),
+            Cleanup::All => write!(f, "all"),
+        }
+    }
+}
+
+impl FromStr for Cleanup {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "off" => Ok(Cleanup::Off),
+            "recent" => Ok(Cleanup::Recent),
+            "all" => Ok(Cleanup::All),
+            _ => Err(anyhow!("Unknown clean up {value}, use off, recent or all")),
+        }
+    }
+}
+
+// lifts the action taken on the user and, for `ham`, also marks the message as not spam
+#[derive(Debug, PartialEq)]
+struct Undo {
+    action: Action,
+    chat_id: i64,
+    user_id: i64,
+    not_spam: bool,
+}
+
+impl Undo {
+    fn parse(data: &str) -> Option<Self> {
+        let mut parts = data.split(':');
+        let kind = parts.next()?;
+        let chat_id = parts.next()?.parse::<i64>().ok()?;
+        let user_id = parts.next()?.parse::<i64>().ok()?;
+        let action = parts.next();
+        if parts.next().is_some() {
+            return None;
+        }
+        let (action, not_spam) = match (kind, action) {
+            ("unban", None) => (Action::Ban, false),
+            ("unmute", None) => (Action::Mute, false),
+            ("ham", None) => (Action::Ban, true), // reports sent before the action was included
+            ("ham", Some(action)) => (action.parse::<Action>().ok()?, true),
+            _ => return None,
+        };
+        Some(Self {
+            action,
+            chat_id,
+            user_id,
+            not_spam,
+        })
+    }
+}
+
+pub struct Report {
+    pub chat_id: i64,
+    pub chat_title: Option<String>,
+    pub user_id: i64,
+    pub username: Option<String>,
+    pub action: Action,
+    pub category: String,
+    pub score: Option<f32>,
+    pub rules: Vec<String>,
+    pub contents: Option<String>,
+}
+
+impl Report {
+    fn text(&self) -> String {
+        let chat = match &self.chat_title {
+            Some(title) => format!("{title} ({})", self.chat_id),
+            None => self.chat_id.to_string(),
+        };
+        let user = match &self.username {
+            Some(username) => format!("@{username} ({})", self.user_id),
+            None => self.user_id.to_string(),
+        };
+        let score = match self.score {
+            Some(score) => format!("{score:.3}"),
+            None => "n/a".to_string(),
+        };
+        let mut lines = vec![
+            "🚫 AirNope report".to_string(),
+            format!("Chat: {chat}"),
+            format!("User: {user}"),
+            format!("Action: {}", self.action.description()),
+            format!("Category: {}", self.category),
+            format!("Score: {score}"),
+        ];
+        if !self.rules.is_empty() {
+            lines.push(format!("Rules: {}", self.rules.join(", ")));
+        }
+        if let Some(contents) = &self.contents {
+            lines.push(format!("Preview: {}", redacted(contents)));
+        }
+        lines.join("\n")
+    }
+
+    fn keyboard(&self) -> Option<InlineKeyboardMarkup> {
+        let lift = match self.action {
+            Action::Ban => Some(("Unban", "unban")),
+            Action::Mute => Some(("Unmute", "unmute")),
+            Action::Delete => None,
+            _ => return None,
+        };
+        let ids = format!("{}:{}", self.chat_id, self.user_id);
+        let buttons = lift
+            .map(|(text, kind)| InlineKeyboardButton {
+                text: text.to_string(),
+                callback_data: format!("{kind}:{ids}"),
+            })
+            .into_iter()
+            .chain([InlineKeyboardButton {
+                text: "Mark as not spam".to_string(),
+                callback_data: format!("ham:{ids}:{}", self.action),
+            }])
+            .collect();
+        Some(InlineKeyboardMarkup {
+            inline_keyboard: vec![buttons],
+        })
+    }
+}
+
+pub struct ModerationLog {
+    chat_id: Option<i64>,
+    forward: bool,
+    ham_path: Option<PathBuf>,
+}
+
+impl ModerationLog {
+    pub fn new() -> Result<Self> {
+        let chat_id = env::var(LOG_CHAT_ID)
+            .ok()
+            .map(|value| value.parse::<i64>())
+            .transpose()?;
+        let forward = env::var(LOG_FORWARD).is_ok_and(|value| value == "true");
+        let ham_path = env::var(HAM_PATH).ok().map(PathBuf::from);
+        if let Some(id) = chat_id {
+            log::info!("Moderation log enabled in chat {id}");
+        }
+        Ok(Self {
+            chat_id,
+            forward,
+            ham_path,
+        })
+    }
+
+    fn destination(&self, custom: Option<i64>) -> Option<i64> {
+        custom.or(self.chat_id)
+    }
+
+    pub async fn forward(
+        &self,
+        client: &Client,
+        destination: Option<i64>,
+        chat_id: i64,
+        message_id: i64,
+    ) -> Result<()> {
+        if let Some(log_chat_id) = self.destination(destination) {
+            if self.forward {
+                client
+                    .forward_message(log_chat_id, chat_id, message_id)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    // only the fingerprint is kept, so the message can still be forgotten after a restart, and the
+    // contents only if they can be saved as an example of not spam
+    pub async fn report(
+        &self,
+        client: &Client,
+        storage: &AsyncStorage,
+        destination: Option<i64>,
+        report: &Report,
+    ) -> Result<()> {
+        if let Some(log_chat_id) = self.destination(destination) {
+            let id = client
+                .send_message(log_chat_id, &report.text(), report.keyboard())
+                .await?;
+            if let Some(contents) = &report.contents {
+                let reported = Reported {
+                    fingerprint: simhash(contents),
+                    contents: self.ham_path.as_ref().map(|_| contents.clone()),
+                };
+                storage
+                    .run(move |storage| storage.save_report(log_chat_id, id, &reported))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn notify(
+        &self,
+        client: &Client,
+        destination: Option<i64>,
+        text: &str,
+    ) -> Result<()> {
+        if let Some(log_chat_id) = self.destination(destination) {
+            client.send_message(log_chat_id, text, None).await?;
+        }
+        Ok(())
+    }
+
+    // returns whether the message was saved
+    async fn record_ham(&self, contents: &str) -> Result<bool> {
+        let Some(dir) = &self.ham_path else {
+            log::info!("Message marked as not spam, but {HAM_PATH} is not set");
+            return Ok(false);
+        };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = dir.join(format!("not_spam_{timestamp}.txt"));
+        fs::create_dir_all(dir).await?;
+        fs::write(&path, contents).await?;
+        log::info!("Message marked as not spam saved to {}", path.display());
+        Ok(true)
+    }
+
+    // undoing also makes AirNope forget the user and the message, so they are not acted on again
+    // in other chats
+    async fn undo(
+        &self,
+        client: &Client,
+        storage: &AsyncStorage,
+        admin_id: i64,
+        report_id: Option<(i64, i64)>,
+        data: &str,
+    ) -> Result<String> {
+        let undo = Undo::parse(data).ok_or(anyhow!("Invalid callback data: {data}"))?;
+        let (chat_id, user_id) = (undo.chat_id, undo.user_id);
+        if !client.is_admin(chat_id, admin_id).await? {
+            return Ok("Only admins of that chat can do that".to_string());
+        }
+        let lifted = match undo.action {
+            Action::Ban => {
+                client.unban_chat_member(chat_id, user_id).await?;
+                Some("User unbanned")
+            }
+            Action::Mute => {
+                client
+                    .restrict_chat_member(chat_id, user_id, ChatPermissions::unrestricted(), None)
+                    .await?;
+                Some("User unmuted")
+            }
+            _ => None,
+        };
+        log::info!(
+            "Undid {} of {user_id} in chat {chat_id} by {admin_id}",
+            undo.action
+        );
+        storage
+            .run(move |storage| storage.forget_spammer(user_id))
+            .await?;
+        if !undo.not_spam {
+            return Ok(lifted.unwrap_or("Nothing to undo").to_string());
+        }
+        let reported = match report_id {
+            Some((log_chat_id, message_id)) => {
+                storage
+                    .run(move |storage| storage.take_report(log_chat_id, message_id))
+                    .await?
+            }
+            None => None,
+        };
+        let outcome = match reported {
+            Some(reported) => {
+                if let Some(fingerprint) = reported.fingerprint {
+                    storage
+                        .run(move |storage| storage.forget_spam(fingerprint))
+                        .await?;
+                }
+                let saved = match &reported.contents {
+                    Some(contents) => self.record_ham(contents).await?,
+                    None => false,
+                };
+                if saved {
+                    "Message marked as not spam"
+                } else {
+                    "Message forgotten, but not saved as an example of not spam"
+                }
+            }
+            None => {
+                log::warn!("Message reported in {report_id:?} is no longer available");
+                "The message is no longer available"
+            }
+        };
+        Ok(match lifted {
+            Some(lifted) => format!("{lifted}. {outcome}"),
+            None => outcome.to_string(),
+        })
+    }
+
+    pub async fn handle_callback(
+        &self,
+        client: &Client,
+        storage: &AsyncStorage,
+        query_id: &str,
+        admin_id: i64,
+        report_id: Option<(i64, i64)>,
+        data: &str,
+    ) -> Result<()> {
+        let answer = match self.undo(client, storage, admin_id, report_id, data).await {
+            Ok(answer) => answer,
+            Err(e) => {
+                log::error!("Error handling moderation log action: {e}");
+                "Something went wrong, please try again".to_string()
+            }
+        };
+        client.answer_callback_query(query_id, &answer).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted() {
+        let got = redacted("Claim at https://scam.xyz/claim or t.me/scam_bot, ask @scammer");
+        assert_eq!(got, "Claim at [link] or [link] ask @[user]");
+    }
+
+    #[test]
+    fn test_undo_parse() {
+        let undo = |action, not_spam| {
+            Some(Undo {
+                action,
+                chat_id: -1001061765207,
+                user_id: 6873835434,
+                not_spam,
+            })
+        };
+        assert_eq!(
+            Undo::parse("unban:-1001061765207:6873835434"),
+            undo(Action::Ban, false)
+        );
+        assert_eq!(
+            Undo::parse("unmute:-1001061765207:6873835434"),
+            undo(Action::Mute, false)
+        );
+        assert_eq!(
+            Undo::parse("ham:-1001061765207:6873835434"),
+            undo(Action::Ban, true)
+        );
+        assert_eq!(
+            Undo::parse("ham:-1001061765207:6873835434:delete"),
+            undo(Action::Delete, true)
+        );
+        assert_eq!(Undo::parse("ban:-1001061765207:6873835434"), None);
+        assert_eq!(Undo::parse("unban:-1001061765207"), None);
+        assert_eq!(Undo::parse("unban:-1001061765207:6873835434:42"), None);
+        assert_eq!(Undo::parse("ham:-1001061765207:6873835434:react"), None);
+    }
+
+    #[test]
+    fn test_report_text() {
+        let report = Report {
+            chat_id: -1001061765207,
+            chat_title: Some("Dados Abertos .BR".to_string()),
+            user_id: 6873835434,
+            username: Some("Brian7547".to_string()),
+            action: Action::Ban,
+            category: "crypto airdrop".to_string(),
+            score: Some(0.7342),
+            rules: vec!["airdrop".to_string(), "claim".to_string()],
+            contents: Some("Claim your airdrop at https://scam.xyz".to_string()),
+        };
+        assert_eq!(
+            report.text(),
+            "🚫 AirNope report\n\
+            Chat: Dados Abertos .BR (-1001061765207)\n\
+            User: @Brian7547 (6873835434)\n\
+            Action: deleted message and banned user\n\
+            Category: crypto airdrop\n\
+            Score: 0.734\n\
+            Rules: airdrop, claim\n\
+            Preview: Claim your airdrop at [link]"
+        );
+    }
+
+    #[test]
+    fn test_report_keyboard() {
+        let report = |action| Report {
+            chat_id: -42,
+            chat_title: None,
+            user_id: 42,
+            username: None,
+            action,
+            category: "crypto airdrop".to_string(),
+            score: None,
+            rules: vec![],
+            contents: None,
+        };
+        let buttons = |action| {
+            report(action).keyboard().map(|keyboard| {
+                keyboard.inline_keyboard[0]
+                    .iter()
+                    .map(|button| button.callback_data.clone())
+                    .collect::<Vec<String>>()
+            })
+        };
+        assert_eq!(
+            buttons(Action::Ban).unwrap(),
+            vec!["unban:-42:42", "ham:-42:42:ban"]
+        );
+        assert_eq!(
+            buttons(Action::Mute).unwrap(),
+            vec!["unmute:-42:42", "ham:-42:42:mute"]
+        );
+        assert_eq!(buttons(Action::Delete).unwrap(), vec!["ham:-42:42:delete"]);
+        assert_eq!(buttons(Action::Shadow), None);
+        for data in buttons(Action::Mute).unwrap() {
+            assert_eq!(Undo::parse(&data).unwrap().action, Action::Mute);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use moka::future::Cache;
+use std::time::{Duration, Instant};
+
+const CAPACITY: u64 = 65_536;
+
+// keeps only the IDs of users who recently joined a chat, when they joined and how many messages
+// they sent, and forgets them once the window is over, so nothing about them outlives it
+pub struct Newcomers {
+    messages: u32,
+    window: Duration,
+    members: Cache<(i64, i64), (Instant, u32)>,
+}
+
+impl Newcomers {
+    pub fn new(messages: u32, window: Duration) -> Self {
+        let members = Cache::builder()
+            .max_capacity(CAPACITY)
+            .time_to_live(window)
+            .build();
+        Self {
+            messages,
+            window,
+            members,
+        }
+    }
+
+    pub async fn joined(&self, chat_id: i64, user_id: i64) {
+        self.members
+            .insert((chat_id, user_id), (Instant::now(), 0))
+            .await;
+    }
+
+    // counts the message and tells whether it is one of the first ones the user sent, within the
+    // time window after they joined (whichever ends first)
+    pub async fn is_new(&self, chat_id: i64, user_id: i64) -> bool {
+        let key = (chat_id, user_id);
+        let Some((joined, count)) = self.members.get(&key).await else {
+            return false;
+        };
+        if joined.elapsed() >= self.window || count >= self.messages {
+            self.members.invalidate(&key).await;
+            return false;
+        }
+        self.members.insert(key, (joined, count + 1)).await;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_is_new_for_the_first_messages() {
+        let newcomers = Newcomers::new(2, Duration::from_secs(60));
+        assert!(!newcomers.is_new(-42, 42).await);
+        newcomers.joined(-42, 42).await;
+        assert!(newcomers.is_new(-42, 42).await);
+        assert!(newcomers.is_new(-42, 42).await);
+        assert!(!newcomers.is_new(-42, 42).await);
+        assert!(!newcomers.is_new(-1, 42).await);
+    }
+
+    #[tokio::test]
+    async fn test_is_new_within_the_window() {
+        let newcomers = Newcomers::new(3, Duration::from_millis(50));
+        newcomers.joined(-42, 42).await;
+        assert!(newcomers.is_new(-42, 42).await);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!newcomers.is_new(-42, 42).await);
+    }
+}
@@ -0,0 +1,195 @@
+use airnope::fingerprint::{distance, MAX_DISTANCE};
+use moka::future::Cache;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const CAPACITY: u64 = 16_384;
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq)]
+pub enum Trigger {
+    Messages(usize),
+    Joins(usize),
+    Similar(usize),
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Messages(count) => write!(f, "{count} messages in a minute"),
+            Trigger::Joins(count) => write!(f, "{count} new members in a minute"),
+            Trigger::Similar(count) => write!(f, "{count} similar messages in a minute"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Activity {
+    messages: VecDeque<(Instant, Option<u64>)>,
+    joins: VecDeque<Instant>,
+    raid: Option<Instant>,
+}
+
+impl Activity {
+    fn forget_old(&mut self, now: Instant) {
+        while self
+            .messages
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > WINDOW)
+        {
+            self.messages.pop_front();
+        }
+        while self
+            .joins
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > WINDOW)
+        {
+            self.joins.pop_front();
+        }
+    }
+}
+
+pub struct Limits {
+    pub messages: usize,
+    pub joins: usize,
+    pub similar: usize,
+    pub duration: Duration,
+}
+
+// keeps, in memory only, when messages were sent and members joined in each chat over the last
+// minute, and the fingerprints of those messages, to tell when a chat is under a raid
+pub struct Raids {
+    limits: Limits,
+    chats: Cache<i64, Arc<Mutex<Activity>>>,
+}
+
+impl Raids {
+    pub fn new(limits: Limits) -> Self {
+        let chats = Cache::builder()
+            .max_capacity(CAPACITY)
+            .time_to_idle(WINDOW + limits.duration)
+            .build();
+        Self { limits, chats }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.limits.duration
+    }
+
+    async fn activity(&self, chat_id: i64) -> Arc<Mutex<Activity>> {
+        self.chats.get_with(chat_id, async { Arc::default() }).await
+    }
+
+    // a raid lasts for a fixed time after it is detected, and a new one can start right after
+    fn start(&self, activity: &mut Activity, now: Instant, trigger: Trigger) -> Option<Trigger> {
+        if activity
+            .raid
+            .is_some_and(|started| now.duration_since(started) < self.limits.duration)
+        {
+            return None;
+        }
+        activity.raid = Some(now);
+        Some(trigger)
+    }
+
+    // returns what triggered the raid, only when a new one starts
+    pub async fn joined(&self, chat_id: i64) -> Option<Trigger> {
+        let activity = self.activity(chat_id).await;
+        let mut activity = activity.lock().ok()?;
+        let now = Instant::now();
+        activity.forget_old(now);
+        activity.joins.push_back(now);
+        let joins = activity.joins.len();
+        if joins < self.limits.joins {
+            return None;
+        }
+        self.start(&mut activity, now, Trigger::Joins(joins))
+    }
+
+    // returns what triggered the raid, only when a new one starts
+    pub async fn message(&self, chat_id: i64, fingerprint: Option<u64>) -> Option<Trigger> {
+        let activity = self.activity(chat_id).await;
+        let mut activity = activity.lock().ok()?;
+        let now = Instant::now();
+        activity.forget_old(now);
+        activity.messages.push_back((now, fingerprint));
+        let messages = activity.messages.len();
+        if messages >= self.limits.messages {
+            return self.start(&mut activity, now, Trigger::Messages(messages));
+        }
+        let fingerprint = fingerprint?;
+        let similar = activity
+            .messages
+            .iter()
+            .filter_map(|(_, other)| *other)
+            .filter(|other| distance(fingerprint, *other) <= MAX_DISTANCE)
+            .count();
+        if similar < self.limits.similar {
+            return None;
+        }
+        self.start(&mut activity, now, Trigger::Similar(similar))
+    }
+
+    pub async fn is_raided(&self, chat_id: i64) -> bool {
+        let Some(activity) = self.chats.get(&chat_id).await else {
+            return false;
+        };
+        activity.lock().is_ok_and(|activity| {
+            activity
+                .raid
+                .is_some_and(|started| started.elapsed() < self.limits.duration)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raids(duration: Duration) -> Raids {
+        Raids::new(Limits {
+            messages: 6,
+            joins: 3,
+            similar: 3,
+            duration,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_raid_triggers() {
+        let raids = raids(Duration::from_secs(60));
+        assert_eq!(raids.joined(-42).await, None);
+        assert_eq!(raids.joined(-42).await, None);
+        assert_eq!(raids.joined(-42).await, Some(Trigger::Joins(3)));
+        assert_eq!(raids.joined(-42).await, None); // already under a raid
+        assert!(raids.is_raided(-42).await);
+        assert!(!raids.is_raided(-1).await);
+
+        for fingerprint in [Some(1), Some(0xffff), None] {
+            assert_eq!(raids.message(-1, fingerprint).await, None);
+        }
+        assert_eq!(raids.message(-1, Some(3)).await, None);
+        assert_eq!(raids.message(-1, Some(7)).await, Some(Trigger::Similar(3)));
+
+        for _ in 0..5 {
+            assert_eq!(raids.message(-2, None).await, None);
+        }
+        assert_eq!(raids.message(-2, None).await, Some(Trigger::Messages(6)));
+    }
+
+    #[tokio::test]
+    async fn test_raid_ends() {
+        let raids = raids(Duration::from_millis(50));
+        for _ in 0..3 {
+            raids.joined(-42).await;
+        }
+        assert!(raids.is_raided(-42).await);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!raids.is_raided(-42).await);
+        assert_eq!(raids.joined(-42).await, Some(Trigger::Joins(4)));
+    }
+}
@@ -0,0 +1,23 @@
+use crate::modlog::{Action, Cleanup, JoinAction};
+use airnope::zsc::THRESHOLD;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    pub threshold: Option<f32>,
+    pub action: Action,
+    pub shadow: bool,
+    pub log_chat_id: Option<i64>,
+    pub join: Option<JoinAction>,
+    pub approve_requests: bool,
+    pub newcomers: bool,
+    pub shared_bans: bool,
+    pub cleanup: Cleanup,
+}
+
+impl ChatSettings {
+    pub fn threshold(&self) -> f32 {
+        self.threshold.unwrap_or(THRESHOLD)
+    }
+}
@@ -0,0 +1,688 @@
+use crate::{modlog::Action, settings::ChatSettings};
+use airnope::fingerprint::{bands, distance, MAX_DISTANCE};
+use anyhow::{anyhow, Result};
+use dirs::data_dir;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::task::spawn_blocking;
+
+const DATABASE_PATH: &str = "AIRNOPE_DATABASE_PATH";
+const STORE_MESSAGES: &str = "AIRNOPE_STORE_MESSAGES";
+const SPAM_MEMORY_HOURS: &str = "AIRNOPE_SPAM_MEMORY_HOURS";
+const DEFAULT_SPAM_MEMORY_HOURS: i64 = 72;
+const VERDICT_RETENTION_DAYS: &str = "AIRNOPE_VERDICT_RETENTION_DAYS";
+const DEFAULT_VERDICT_RETENTION_DAYS: i64 = 30;
+// how long the buttons of a report in the moderation log can still mark the message as not spam
+const REPORT_TTL: i64 = 7 * 24 * 60 * 60;
+
+// each migration runs once, in order, and the index of the last one applied is saved as the
+// database `user_version`, so never edit or remove a migration, only append new ones
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE chat_settings (
+        chat_id INTEGER PRIMARY KEY,
+        settings TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    );",
+    "CREATE TABLE verdicts (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        message_id INTEGER NOT NULL,
+        category TEXT NOT NULL,
+        score REAL,
+        action TEXT NOT NULL,
+        content_hash TEXT,
+        content TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX verdicts_chat_id ON verdicts (chat_id, created_at);
+    CREATE INDEX verdicts_created_at ON verdicts (created_at);",
+    "CREATE TABLE fingerprints (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        fingerprint INTEGER NOT NULL,
+        band0 INTEGER NOT NULL,
+        band1 INTEGER NOT NULL,
+        band2 INTEGER NOT NULL,
+        band3 INTEGER NOT NULL,
+        band4 INTEGER NOT NULL,
+        band5 INTEGER NOT NULL,
+        band6 INTEGER NOT NULL,
+        band7 INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX fingerprints_band0 ON fingerprints (band0);
+    CREATE INDEX fingerprints_band1 ON fingerprints (band1);
+    CREATE INDEX fingerprints_band2 ON fingerprints (band2);
+    CREATE INDEX fingerprints_band3 ON fingerprints (band3);
+    CREATE INDEX fingerprints_band4 ON fingerprints (band4);
+    CREATE INDEX fingerprints_band5 ON fingerprints (band5);
+    CREATE INDEX fingerprints_band6 ON fingerprints (band6);
+    CREATE INDEX fingerprints_band7 ON fingerprints (band7);
+    CREATE INDEX fingerprints_created_at ON fingerprints (created_at);
+    CREATE TABLE spammers (
+        user_id INTEGER PRIMARY KEY,
+        created_at INTEGER NOT NULL
+    );",
+    "CREATE TABLE reports (
+        chat_id INTEGER NOT NULL,
+        message_id INTEGER NOT NULL,
+        fingerprint INTEGER,
+        contents TEXT,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, message_id)
+    );
+    CREATE INDEX reports_created_at ON reports (created_at);",
+];
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+pub fn hash(contents: &str) -> String {
+    Sha256::digest(contents.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Verdict {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub message_id: i64,
+    pub category: String,
+    pub score: Option<f32>,
+    pub action: Action,
+    pub contents: Option<String>,
+}
+
+// what undoing a report needs: the fingerprint to forget the spam, and the contents only to save
+// them as an example of not spam
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reported {
+    pub fingerprint: Option<u64>,
+    pub contents: Option<String>,
+}
+
+pub trait Storage: Send + Sync {
+    fn chat_settings(&self, chat_id: i64) -> Result<ChatSettings>;
+    fn save_chat_settings(&self, chat_id: i64, settings: &ChatSettings) -> Result<()>;
+    fn record_verdict(&self, verdict: &Verdict) -> Result<()>;
+    // fingerprints of confirmed spam and users banned for spam are remembered for a while, so
+    // the same spam is recognised in any chat
+    fn remember_spam(&self, fingerprint: u64) -> Result<()>;
+    fn is_known_spam(&self, fingerprint: u64) -> Result<bool>;
+    fn forget_spam(&self, fingerprint: u64) -> Result<()>;
+    fn remember_spammer(&self, user_id: i64) -> Result<()>;
+    fn is_known_spammer(&self, user_id: i64) -> Result<bool>;
+    fn forget_spammer(&self, user_id: i64) -> Result<()>;
+    // messages reported in the moderation log, identified by the report
+    fn save_report(&self, chat_id: i64, message_id: i64, reported: &Reported) -> Result<()>;
+    fn take_report(&self, chat_id: i64, message_id: i64) -> Result<Option<Reported>>;
+}
+
+// rusqlite blocks the thread, so async code uses the storage only through the blocking pool
+#[derive(Clone)]
+pub struct AsyncStorage(Arc<dyn Storage>);
+
+impl AsyncStorage {
+    pub fn new(storage: impl Storage + 'static) -> Self {
+        Self(Arc::new(storage))
+    }
+
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Storage) -> Result<T> + Send + 'static,
+    {
+        let storage = self.0.clone();
+        spawn_blocking(move || f(storage.as_ref())).await?
+    }
+}
+
+pub struct Sqlite {
+    connection: Mutex<Connection>,
+    store_messages: bool,
+    memory: i64,
+    retention: i64,
+}
+
+impl Sqlite {
+    pub fn new() -> Result<Self> {
+        let path = match env::var(DATABASE_PATH) {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => {
+                let mut path = data_dir().ok_or(anyhow!("Could not find the data directory"))?;
+                path.push("airnope");
+                path.push("airnope.db");
+                path
+            }
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        log::info!("Using database at {}", path.display());
+        let store_messages = env::var(STORE_MESSAGES).is_ok_and(|value| value == "true");
+        if store_messages {
+            log::warn!("Storing the contents of messages detected as spam");
+        }
+        let hours = match env::var(SPAM_MEMORY_HOURS) {
+            Ok(value) => value
+                .parse::<i64>()
+                .map_err(|e| anyhow!("Invalid value for {SPAM_MEMORY_HOURS}: {e}"))?,
+            Err(_) => DEFAULT_SPAM_MEMORY_HOURS,
+        };
+        let days = match env::var(VERDICT_RETENTION_DAYS) {
+            Ok(value) => value
+                .parse::<i64>()
+                .map_err(|e| anyhow!("Invalid value for {VERDICT_RETENTION_DAYS}: {e}"))?,
+            Err(_) => DEFAULT_VERDICT_RETENTION_DAYS,
+        };
+        Self::open(
+            Connection::open(path)?,
+            store_messages,
+            hours * 60 * 60,
+            days * 24 * 60 * 60,
+        )
+    }
+
+    fn open(
+        mut connection: Connection,
+        store_messages: bool,
+        memory: i64,
+        retention: i64,
+    ) -> Result<Self> {
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", idx as i64 + 1)?;
+            transaction.commit()?;
+            log::debug!("Applied database migration #{}", idx + 1);
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+            store_messages,
+            memory,
+            retention,
+        })
+    }
+
+    fn connection(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.connection
+            .lock()
+            .map_err(|e| anyhow!("Database connection is poisoned: {e}"))
+    }
+
+    // IDs of the fingerprints remembered since `since` that are near-duplicates of `fingerprint`
+    fn near_duplicates(&self, fingerprint: u64, since: i64) -> Result<Vec<i64>> {
+        let [band0, band1, band2, band3, band4, band5, band6, band7] = bands(fingerprint);
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT id, fingerprint FROM fingerprints
+            WHERE created_at >= ?1 AND (
+                band0 = ?2 OR band1 = ?3 OR band2 = ?4 OR band3 = ?5
+                OR band4 = ?6 OR band5 = ?7 OR band6 = ?8 OR band7 = ?9
+            )",
+        )?;
+        let rows = statement.query_map(
+            params![since, band0, band1, band2, band3, band4, band5, band6, band7],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )?;
+        let mut ids = vec![];
+        for row in rows {
+            let (id, candidate) = row?;
+            if distance(fingerprint, candidate as u64) <= MAX_DISTANCE {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+impl Storage for Sqlite {
+    fn chat_settings(&self, chat_id: i64) -> Result<ChatSettings> {
+        let settings: Option<String> = self
+            .connection()?
+            .query_row(
+                "SELECT settings FROM chat_settings WHERE chat_id = ?1",
+                params![chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match settings {
+            Some(settings) => Ok(serde_json::from_str(&settings)?),
+            None => Ok(ChatSettings::default()),
+        }
+    }
+
+    fn save_chat_settings(&self, chat_id: i64, settings: &ChatSettings) -> Result<()> {
+        self.connection()?.execute(
+            "INSERT INTO chat_settings (chat_id, settings, updated_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT (chat_id) DO UPDATE SET settings = ?2, updated_at = ?3",
+            params![chat_id, serde_json::to_string(settings)?, now()],
+        )?;
+        Ok(())
+    }
+
+    fn record_verdict(&self, verdict: &Verdict) -> Result<()> {
+        let content = if self.store_messages {
+            verdict.contents.as_deref()
+        } else {
+            None
+        };
+        let connection = self.connection()?;
+        connection.execute(
+            "DELETE FROM verdicts WHERE created_at < ?1",
+            params![now() - self.retention],
+        )?;
+        connection.execute(
+            "INSERT INTO verdicts
+            (chat_id, user_id, message_id, category, score, action, content_hash, content, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                verdict.chat_id,
+                verdict.user_id,
+                verdict.message_id,
+                verdict.category,
+                verdict.score,
+                verdict.action.to_string(),
+                verdict.contents.as_deref().map(hash),
+                content,
+                now(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remember_spam(&self, fingerprint: u64) -> Result<()> {
+        let [band0, band1, band2, band3, band4, band5, band6, band7] = bands(fingerprint);
+        let connection = self.connection()?;
+        connection.execute(
+            "DELETE FROM fingerprints WHERE created_at < ?1",
+            params![now() - self.memory],
+        )?;
+        connection.execute(
+            "INSERT INTO fingerprints
+            (fingerprint, band0, band1, band2, band3, band4, band5, band6, band7, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                fingerprint as i64,
+                band0,
+                band1,
+                band2,
+                band3,
+                band4,
+                band5,
+                band6,
+                band7,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn is_known_spam(&self, fingerprint: u64) -> Result<bool> {
+        Ok(!self
+            .near_duplicates(fingerprint, now() - self.memory)?
+            .is_empty())
+    }
+
+    fn forget_spam(&self, fingerprint: u64) -> Result<()> {
+        let ids = self.near_duplicates(fingerprint, i64::MIN)?;
+        let connection = self.connection()?;
+        for id in ids {
+            connection.execute("DELETE FROM fingerprints WHERE id = ?1", params![id])?;
+        }
+        Ok(())
+    }
+
+    fn remember_spammer(&self, user_id: i64) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "DELETE FROM spammers WHERE created_at < ?1",
+            params![now() - self.memory],
+        )?;
+        connection.execute(
+            "INSERT INTO spammers (user_id, created_at) VALUES (?1, ?2)
+            ON CONFLICT (user_id) DO UPDATE SET created_at = ?2",
+            params![user_id, now()],
+        )?;
+        Ok(())
+    }
+
+    fn is_known_spammer(&self, user_id: i64) -> Result<bool> {
+        let found: Option<i64> = self
+            .connection()?
+            .query_row(
+                "SELECT user_id FROM spammers WHERE user_id = ?1 AND created_at >= ?2",
+                params![user_id, now() - self.memory],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    fn forget_spammer(&self, user_id: i64) -> Result<()> {
+        self.connection()?
+            .execute("DELETE FROM spammers WHERE user_id = ?1", params![user_id])?;
+        Ok(())
+    }
+
+    fn save_report(&self, chat_id: i64, message_id: i64, reported: &Reported) -> Result<()> {
+        let connection = self.connection()?;
+        connection.execute(
+            "DELETE FROM reports WHERE created_at < ?1",
+            params![now() - REPORT_TTL],
+        )?;
+        connection.execute(
+            "INSERT OR REPLACE INTO reports
+            (chat_id, message_id, fingerprint, contents, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                chat_id,
+                message_id,
+                reported.fingerprint.map(|fingerprint| fingerprint as i64),
+                reported.contents,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn take_report(&self, chat_id: i64, message_id: i64) -> Result<Option<Reported>> {
+        let connection = self.connection()?;
+        let reported = connection
+            .query_row(
+                "SELECT fingerprint, contents FROM reports
+                WHERE chat_id = ?1 AND message_id = ?2 AND created_at >= ?3",
+                params![chat_id, message_id, now() - REPORT_TTL],
+                |row| {
+                    Ok(Reported {
+                        fingerprint: row.get::<_, Option<i64>>(0)?.map(|value| value as u64),
+                        contents: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        connection.execute(
+            "DELETE FROM reports WHERE chat_id = ?1 AND message_id = ?2",
+            params![chat_id, message_id],
+        )?;
+        Ok(reported)
+    }
+}
+
+#[cfg(test)]
+pub struct Memory {
+    chats: Mutex<std::collections::HashMap<i64, ChatSettings>>,
+    verdicts: Mutex<Vec<Verdict>>,
+    fingerprints: Mutex<Vec<u64>>,
+    spammers: Mutex<std::collections::HashSet<i64>>,
+    reports: Mutex<std::collections::HashMap<(i64, i64), Reported>>,
+}
+
+#[cfg(test)]
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            chats: Mutex::new(std::collections::HashMap::new()),
+            verdicts: Mutex::new(vec![]),
+            fingerprints: Mutex::new(vec![]),
+            spammers: Mutex::new(std::collections::HashSet::new()),
+            reports: Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Storage for Memory {
+    fn chat_settings(&self, chat_id: i64) -> Result<ChatSettings> {
+        Ok(self
+            .chats
+            .lock()
+            .unwrap()
+            .get(&chat_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn save_chat_settings(&self, chat_id: i64, settings: &ChatSettings) -> Result<()> {
+        self.chats.lock().unwrap().insert(chat_id, settings.clone());
+        Ok(())
+    }
+
+    fn record_verdict(&self, verdict: &Verdict) -> Result<()> {
+        self.verdicts.lock().unwrap().push(verdict.clone());
+        Ok(())
+    }
+
+    fn remember_spam(&self, fingerprint: u64) -> Result<()> {
+        self.fingerprints.lock().unwrap().push(fingerprint);
+        Ok(())
+    }
+
+    fn is_known_spam(&self, fingerprint: u64) -> Result<bool> {
+        Ok(self
+            .fingerprints
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|known| distance(fingerprint, *known) <= MAX_DISTANCE))
+    }
+
+    fn forget_spam(&self, fingerprint: u64) -> Result<()> {
+        self.fingerprints
+            .lock()
+            .unwrap()
+            .retain(|known| distance(fingerprint, *known) > MAX_DISTANCE);
+        Ok(())
+    }
+
+    fn remember_spammer(&self, user_id: i64) -> Result<()> {
+        self.spammers.lock().unwrap().insert(user_id);
+        Ok(())
+    }
+
+    fn is_known_spammer(&self, user_id: i64) -> Result<bool> {
+        Ok(self.spammers.lock().unwrap().contains(&user_id))
+    }
+
+    fn forget_spammer(&self, user_id: i64) -> Result<()> {
+        self.spammers.lock().unwrap().remove(&user_id);
+        Ok(())
+    }
+
+    fn save_report(&self, chat_id: i64, message_id: i64, reported: &Reported) -> Result<()> {
+        self.reports
+            .lock()
+            .unwrap()
+            .insert((chat_id, message_id), reported.clone());
+        Ok(())
+    }
+
+    fn take_report(&self, chat_id: i64, message_id: i64) -> Result<Option<Reported>> {
+        Ok(self.reports.lock().unwrap().remove(&(chat_id, message_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMORY: i64 = 60 * 60;
+    const RETENTION: i64 = 24 * 60 * 60;
+
+    fn sqlite(store_messages: bool) -> Sqlite {
+        Sqlite::open(
+            Connection::open_in_memory().unwrap(),
+            store_messages,
+            MEMORY,
+            RETENTION,
+        )
+        .unwrap()
+    }
+
+    fn verdict() -> Verdict {
+        Verdict {
+            chat_id: -42,
+            user_id: 42,
+            message_id: 1,
+            category: "crypto airdrop".to_string(),
+            score: Some(0.75),
+            action: Action::Ban,
+            contents: Some("claim your airdrop".to_string()),
+        }
+    }
+
+    fn assert_chat_settings(storage: &dyn Storage) {
+        assert_eq!(storage.chat_settings(-42).unwrap(), ChatSettings::default());
+        let settings = ChatSettings {
+            threshold: Some(0.6),
+            action: Action::Mute,
+            ..Default::default()
+        };
+        storage.save_chat_settings(-42, &settings).unwrap();
+        assert_eq!(storage.chat_settings(-42).unwrap(), settings);
+        assert_eq!(storage.chat_settings(-1).unwrap(), ChatSettings::default());
+    }
+
+    #[test]
+    fn test_chat_settings() {
+        assert_chat_settings(&Memory::new());
+        assert_chat_settings(&sqlite(false));
+    }
+
+    #[test]
+    fn test_migrations_are_applied_once() {
+        let connection = Connection::open_in_memory().unwrap();
+        let storage = Sqlite::open(connection, false, MEMORY, RETENTION).unwrap();
+        let connection = storage.connection.into_inner().unwrap();
+        let storage = Sqlite::open(connection, false, MEMORY, RETENTION).unwrap();
+        let version: i64 = storage
+            .connection()
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_record_verdict_stores_only_hash_by_default() {
+        for (store_messages, expected) in [(false, None), (true, Some("claim your airdrop"))] {
+            let storage = sqlite(store_messages);
+            storage.record_verdict(&verdict()).unwrap();
+            let (content_hash, content): (String, Option<String>) = storage
+                .connection()
+                .unwrap()
+                .query_row("SELECT content_hash, content FROM verdicts", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .unwrap();
+            assert_eq!(content_hash, hash("claim your airdrop"));
+            assert_eq!(content.as_deref(), expected);
+        }
+    }
+
+    #[test]
+    fn test_old_verdicts_are_deleted() {
+        let storage =
+            Sqlite::open(Connection::open_in_memory().unwrap(), false, MEMORY, -1).unwrap();
+        storage.record_verdict(&verdict()).unwrap();
+        storage.record_verdict(&verdict()).unwrap();
+        let count: i64 = storage
+            .connection()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM verdicts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_async_storage() {
+        let storage = AsyncStorage::new(sqlite(false));
+        storage
+            .run(|storage| storage.remember_spammer(42))
+            .await
+            .unwrap();
+        assert!(storage
+            .run(|storage| storage.is_known_spammer(42))
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    fn test_memory_record_verdict() {
+        let storage = Memory::new();
+        storage.record_verdict(&verdict()).unwrap();
+        assert_eq!(storage.verdicts.lock().unwrap().clone(), vec![verdict()]);
+    }
+
+    fn assert_spam_memory(storage: &dyn Storage) {
+        let fingerprint = 0x0123_4567_89ab_cdef;
+        assert!(!storage.is_known_spam(fingerprint).unwrap());
+        storage.remember_spam(fingerprint).unwrap();
+        assert!(storage.is_known_spam(fingerprint).unwrap());
+        assert!(storage.is_known_spam(fingerprint ^ 0b101).unwrap());
+        assert!(!storage
+            .is_known_spam(fingerprint ^ 0x0101_0101_0101_0101)
+            .unwrap());
+        storage.forget_spam(fingerprint ^ 0b1).unwrap();
+        assert!(!storage.is_known_spam(fingerprint).unwrap());
+
+        assert!(!storage.is_known_spammer(42).unwrap());
+        storage.remember_spammer(42).unwrap();
+        storage.remember_spammer(42).unwrap();
+        assert!(storage.is_known_spammer(42).unwrap());
+        storage.forget_spammer(42).unwrap();
+        assert!(!storage.is_known_spammer(42).unwrap());
+    }
+
+    #[test]
+    fn test_spam_memory() {
+        assert_spam_memory(&Memory::new());
+        assert_spam_memory(&sqlite(false));
+    }
+
+    fn assert_reports(storage: &dyn Storage) {
+        let reported = Reported {
+            fingerprint: Some(u64::MAX),
+            contents: None,
+        };
+        storage.save_report(-42, 1, &reported).unwrap();
+        assert_eq!(storage.take_report(-42, 2).unwrap(), None);
+        assert_eq!(storage.take_report(-42, 1).unwrap(), Some(reported));
+        assert_eq!(storage.take_report(-42, 1).unwrap(), None);
+        let reported = Reported {
+            fingerprint: None,
+            contents: Some("claim your airdrop".to_string()),
+        };
+        storage.save_report(-42, 2, &reported).unwrap();
+        assert_eq!(storage.take_report(-42, 2).unwrap(), Some(reported));
+    }
+
+    #[test]
+    fn test_reports() {
+        assert_reports(&Memory::new());
+        assert_reports(&sqlite(false));
+    }
+
+    #[test]
+    fn test_spam_memory_expires() {
+        let storage =
+            Sqlite::open(Connection::open_in_memory().unwrap(), false, -1, RETENTION).unwrap();
+        storage.remember_spam(42).unwrap();
+        storage.remember_spammer(42).unwrap();
+        assert!(!storage.is_known_spam(42).unwrap());
+        assert!(!storage.is_known_spammer(42).unwrap());
+    }
+}
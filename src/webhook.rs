@@ -1,20 +1,104 @@
+use crate::{
+    albums::Albums,
+    commands,
+    history::History,
+    modlog::{Action, Cleanup, ModerationLog, Report},
+    newcomers::Newcomers,
+    raids::{Limits, Raids, Trigger},
+    settings::ChatSettings,
+    storage::{AsyncStorage, Sqlite, Storage, Verdict},
+};
 use actix_web::{
     middleware::Logger,
     web::{self, Bytes},
     App, HttpRequest, HttpResponse, HttpServer,
 };
-use airnope::{embeddings::Embeddings, is_spam, telegram::Client};
-use anyhow::{anyhow, Result};
-use futures::try_join;
+use airnope::{
+    embeddings::Embeddings,
+    fingerprint::simhash,
+    images::{self, KnownImages},
+    is_spam_with_custom_classifier, is_spam_without_regex_gate,
+    metrics::metrics,
+    ocr::Ocr,
+    telegram::{ChatPermissions, Client},
+    zsc::{ZeroShotClassification, THRESHOLD},
+};
+use anyhow::{anyhow, Context, Result};
+use futures::{future::join_all, try_join};
+use image::DynamicImage;
+use moka::future::Cache;
 use rand::{rng, Rng};
 use serde::{Deserialize, Serialize};
-use std::{env, sync::Arc};
-use tokio::sync::Mutex;
+use std::{
+    env, fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    },
+    sync::{mpsc, Mutex, Semaphore},
+    task::{spawn_blocking, JoinHandle},
+    time::timeout,
+};
 
 const DEFAULT_PORT: u16 = 8000;
 const DEFAULT_HOST_IP: &str = "0.0.0.0";
 const SECRET_CHARS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789_-";
 const DEFAULT_AIRNOPE_HANDLE: &str = "@AirNope_bot";
+const RECENT_UPDATES: u64 = 16_384;
+const RECENT_UPDATES_TTL: Duration = Duration::from_secs(60 * 60);
+const QUEUE_SIZE: &str = "AIRNOPE_QUEUE_SIZE";
+const DEFAULT_QUEUE_SIZE: usize = 1_024;
+const WORKERS: &str = "AIRNOPE_WORKERS";
+const DEFAULT_WORKERS: usize = 8;
+const SHUTDOWN_TIMEOUT: &str = "AIRNOPE_SHUTDOWN_TIMEOUT";
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 25;
+const DELETE_WEBHOOK_ON_SHUTDOWN: &str = "AIRNOPE_DELETE_WEBHOOK_ON_SHUTDOWN";
+const CHECK_BIO: &str = "AIRNOPE_CHECK_BIO";
+const RECENT_JOINS: u64 = 16_384;
+const RECENT_JOINS_TTL: Duration = Duration::from_secs(60);
+const PROBATION_SIZE: u64 = 65_536;
+const PROBATION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const NEWCOMER_MESSAGES: &str = "AIRNOPE_NEWCOMER_MESSAGES";
+const DEFAULT_NEWCOMER_MESSAGES: u32 = 3;
+const NEWCOMER_MINUTES: &str = "AIRNOPE_NEWCOMER_MINUTES";
+const DEFAULT_NEWCOMER_MINUTES: u64 = 60;
+const RAID_MESSAGES: &str = "AIRNOPE_RAID_MESSAGES";
+const DEFAULT_RAID_MESSAGES: usize = 60;
+const RAID_JOINS: &str = "AIRNOPE_RAID_JOINS";
+const DEFAULT_RAID_JOINS: usize = 20;
+const RAID_SIMILAR: &str = "AIRNOPE_RAID_SIMILAR";
+const DEFAULT_RAID_SIMILAR: usize = 5;
+const RAID_MINUTES: &str = "AIRNOPE_RAID_MINUTES";
+const DEFAULT_RAID_MINUTES: u64 = 15;
+const RAID_THRESHOLD_DROP: f32 = 0.1;
+const HISTORY_TTL: Duration = Duration::from_secs(60 * 60);
+const QR_CODES: &str = "AIRNOPE_QR_CODES";
+const IMAGE_MAX_BYTES: &str = "AIRNOPE_IMAGE_MAX_BYTES";
+const DEFAULT_IMAGE_MAX_BYTES: u64 = 1_048_576;
+const IMAGE_TIMEOUT: &str = "AIRNOPE_IMAGE_TIMEOUT";
+const DEFAULT_IMAGE_TIMEOUT: u64 = 5;
+const OCR_TIMEOUT: &str = "AIRNOPE_OCR_TIMEOUT";
+const DEFAULT_OCR_TIMEOUT: u64 = 10;
+const DENIED_BOTS: &str = "AIRNOPE_DENIED_BOTS";
+const DEFAULT_DENIED_BOTS: &str = "safeguard";
+const READINESS_PROBE: &str = "Claim your airdrop now";
+
+fn denied_bots(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|bot| bot.trim().trim_start_matches('@').to_lowercase())
+        .filter(|bot| !bot.is_empty())
+        .collect()
+}
 
 fn random_webhook_secret() -> String {
     let length = rng().random_range(128..=256);
@@ -43,6 +127,31 @@ impl Settings {
 #[derive(Deserialize, Serialize)]
 struct UserOrChat {
     id: i64,
+    username: Option<String>,
+    title: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    #[serde(default)]
+    is_bot: bool,
+}
+
+impl UserOrChat {
+    fn profile(&self) -> Option<String> {
+        let parts = [
+            self.first_name.clone(),
+            self.last_name.clone(),
+            self.username
+                .as_ref()
+                .map(|username| format!("@{username}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<String>>();
+        if parts.is_empty() {
+            return None;
+        }
+        Some(parts.join(" "))
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -61,6 +170,94 @@ struct ForwardOrigin {
     sender_user: Option<SenderUser>,
 }
 
+#[derive(Deserialize, Serialize)]
+struct PhotoSize {
+    file_id: String,
+    file_size: Option<u64>,
+    width: u64,
+    height: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Sticker {
+    thumbnail: Option<PhotoSize>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PollOption {
+    text: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Poll {
+    question: String,
+    options: Vec<PollOption>,
+}
+
+impl Poll {
+    fn text(&self) -> String {
+        let options = self.options.iter().map(|option| option.text.as_str());
+        [self.question.as_str()]
+            .into_iter()
+            .chain(options)
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Contact {
+    phone_number: String,
+    first_name: String,
+    last_name: Option<String>,
+}
+
+impl Contact {
+    fn text(&self) -> String {
+        let name = [Some(self.first_name.as_str()), self.last_name.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        format!("{name}\n{}", self.phone_number)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Venue {
+    title: String,
+    address: String,
+}
+
+impl Venue {
+    fn text(&self) -> String {
+        format!("{}\n{}", self.title, self.address)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct TextQuote {
+    text: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ExternalReplyInfo {
+    chat: Option<Chat>,
+    poll: Option<Poll>,
+    contact: Option<Contact>,
+    venue: Option<Venue>,
+}
+
+impl ExternalReplyInfo {
+    fn texts(&self) -> impl Iterator<Item = String> + '_ {
+        let chat = self.chat.as_ref().and_then(|chat| chat.title.clone());
+        chat.into_iter()
+            .chain(self.poll.as_ref().map(Poll::text))
+            .chain(self.contact.as_ref().map(Contact::text))
+            .chain(self.venue.as_ref().map(Venue::text))
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 struct InlineKeyboard {
     text: Option<String>,
@@ -72,6 +269,109 @@ struct ReplyMarkup {
     inline_keyboard: Option<Vec<Vec<InlineKeyboard>>>,
 }
 
+#[derive(Deserialize, Serialize)]
+struct MessageEntity {
+    #[serde(rename = "type")]
+    kind: String,
+    offset: usize,
+    length: usize,
+    url: Option<String>,
+    user: Option<UserOrChat>,
+}
+
+impl MessageEntity {
+    // offsets and lengths are in UTF-16 code units, as in the Telegram Bot API
+    fn text(&self, text: &str) -> Option<String> {
+        let units = text.encode_utf16().collect::<Vec<u16>>();
+        let slice = units.get(self.offset..self.offset.checked_add(self.length)?)?;
+        String::from_utf16(slice).ok()
+    }
+}
+
+fn remember(
+    storage: &dyn Storage,
+    action: Action,
+    user_id: i64,
+    contents: Option<String>,
+) -> Result<()> {
+    if !action.removes_message() {
+        return Ok(());
+    }
+    if let Some(fingerprint) = contents.as_deref().and_then(simhash) {
+        storage.remember_spam(fingerprint)?;
+    }
+    if action == Action::Ban {
+        storage.remember_spammer(user_id)?;
+    }
+    Ok(())
+}
+
+fn raid_threshold(settings: &ChatSettings) -> f32 {
+    (settings.threshold() - RAID_THRESHOLD_DROP).max(RAID_THRESHOLD_DROP)
+}
+
+async fn classify(
+    embeddings: Arc<Mutex<Embeddings>>,
+    settings: &ChatSettings,
+    txt: &str,
+    entities: &[String],
+    strict: bool,
+) -> Result<Option<Detection>> {
+    let classifier = ZeroShotClassification::default(&embeddings)
+        .await?
+        .with_threshold(settings.threshold());
+    let guess = if strict {
+        is_spam_without_regex_gate(&embeddings, classifier, txt, entities).await
+    } else {
+        is_spam_with_custom_classifier(&embeddings, classifier, txt, entities).await
+    };
+    match guess {
+        Ok(guess) if guess.is_spam => Ok(Some(Detection {
+            category: Category::Airdrop,
+            score: guess.score,
+            rules: guess.rules,
+        })),
+        Ok(_) => Ok(None),
+        Err(e) => {
+            log::error!("Error processing message: {e}");
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Category {
+    DeniedBot(String),
+    Airdrop,
+    Repeat,
+    SharedBan,
+    KnownImage,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Category::DeniedBot(bot) => write!(f, "message from denied bot @{bot}"),
+            Category::Airdrop => write!(f, "crypto airdrop"),
+            Category::Repeat => write!(f, "repeated spam"),
+            Category::SharedBan => write!(f, "user banned for spam in another chat"),
+            Category::KnownImage => write!(f, "known spam image"),
+        }
+    }
+}
+
+struct Detection {
+    category: Category,
+    score: Option<f32>,
+    rules: Vec<String>,
+}
+
+impl Detection {
+    fn is_shareable(&self) -> bool {
+        self.category != Category::SharedBan && self.score.is_none_or(|score| score > THRESHOLD)
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 struct Message {
     message_id: i64,
@@ -81,17 +381,79 @@ struct Message {
     caption: Option<String>,
     reply_to_message: Option<Box<Message>>,
     forward_origin: Option<ForwardOrigin>,
+    via_bot: Option<SenderUser>,
     forward_from_chat: Option<Chat>,
     reply_markup: Option<ReplyMarkup>,
+    entities: Option<Vec<MessageEntity>>,
+    caption_entities: Option<Vec<MessageEntity>>,
+    new_chat_members: Option<Vec<UserOrChat>>,
+    media_group_id: Option<String>,
+    photo: Option<Vec<PhotoSize>>,
+    sticker: Option<Sticker>,
+    poll: Option<Poll>,
+    contact: Option<Contact>,
+    venue: Option<Venue>,
+    quote: Option<TextQuote>,
+    external_reply: Option<ExternalReplyInfo>,
 }
 
 impl Message {
+    fn entities(&self) -> impl Iterator<Item = (&str, &MessageEntity)> {
+        let text = self.text.as_deref().into_iter().flat_map(|text| {
+            self.entities
+                .iter()
+                .flatten()
+                .map(move |entity| (text, entity))
+        });
+        let caption = self.caption.as_deref().into_iter().flat_map(|caption| {
+            self.caption_entities
+                .iter()
+                .flatten()
+                .map(move |entity| (caption, entity))
+        });
+        text.chain(caption)
+    }
+
+    fn links(&self) -> Vec<String> {
+        self.entities()
+            .filter_map(|(text, entity)| match entity.kind.as_str() {
+                "url" => entity.text(text),
+                "text_link" => entity.url.clone(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn mentions(&self) -> Vec<String> {
+        self.entities()
+            .filter_map(|(text, entity)| match entity.kind.as_str() {
+                "mention" => entity.text(text),
+                "text_mention" => entity
+                    .user
+                    .as_ref()
+                    .and_then(|user| user.username.as_ref())
+                    .map(|username| format!("@{username}")),
+                _ => None,
+            })
+            .collect()
+    }
+
     fn contents(&self) -> Option<String> {
         let text = self
             .text
             .as_deref()
             .into_iter()
-            .chain(self.caption.as_deref());
+            .chain(self.caption.as_deref())
+            .chain(self.quote.as_ref().map(|quote| quote.text.as_str()))
+            .map(String::from);
+        let attachments = self
+            .poll
+            .as_ref()
+            .map(Poll::text)
+            .into_iter()
+            .chain(self.contact.as_ref().map(Contact::text))
+            .chain(self.venue.as_ref().map(Venue::text))
+            .chain(self.external_reply.iter().flat_map(|reply| reply.texts()));
         let forward = self
             .forward_origin
             .as_ref()
@@ -116,7 +478,19 @@ impl Message {
                     .into_iter()
                     .chain(keyboard.url.as_deref())
             });
-        let merged = text.chain(forward).chain(buttons).collect::<Vec<&str>>();
+        let mut merged = text
+            .chain(attachments)
+            .chain(forward.chain(buttons).map(String::from))
+            .collect::<Vec<String>>();
+        let hidden = self
+            .links()
+            .into_iter()
+            .chain(self.mentions())
+            .filter(|entity| !merged.iter().any(|part| part.contains(entity.as_str())))
+            .collect::<Vec<String>>();
+        if !hidden.is_empty() {
+            merged.push(hidden.join("\n"));
+        }
         if merged.is_empty() {
             return None;
         }
@@ -124,68 +498,304 @@ impl Message {
         Some(merged.join("\n\n"))
     }
 
-    async fn is_spam(&self, embeddings: Arc<Mutex<Embeddings>>) -> Result<bool> {
-        if let Some(sender) = self
-            .forward_origin
+    fn denied_bot(&self, denied: &[String]) -> Option<String> {
+        self.forward_origin
             .as_ref()
             .and_then(|fw| fw.sender_user.as_ref())
-            .and_then(|u| u.username.as_ref())
-        {
-            if sender == "safeguard" {
-                return Ok(true);
-            }
+            .into_iter()
+            .chain(self.via_bot.as_ref())
+            .filter_map(|bot| bot.username.as_ref())
+            .map(|username| username.to_lowercase())
+            .find(|username| denied.contains(username))
+    }
+
+    async fn detect(
+        &self,
+        embeddings: Arc<Mutex<Embeddings>>,
+        settings: &ChatSettings,
+        strict: bool,
+        extra: &[String],
+        denied_bots: &[String],
+    ) -> Result<Option<Detection>> {
+        if let Some(bot) = self.denied_bot(denied_bots) {
+            metrics().verdicts.with_label_values(&["denied_bot"]).inc();
+            return Ok(Some(Detection {
+                category: Category::DeniedBot(bot),
+                score: None,
+                rules: vec![],
+            }));
         }
-        if let Some(txt) = &self.contents() {
-            match is_spam(&embeddings, txt.as_str()).await {
-                Ok(guess) => {
-                    return Ok(guess.is_spam);
-                }
-                Err(e) => {
-                    log::error!("Error processing message: {e}");
-                }
-            }
+        let txt = self
+            .contents()
+            .into_iter()
+            .chain(extra.iter().cloned())
+            .collect::<Vec<String>>();
+        if txt.is_empty() {
+            return Ok(None);
+        }
+        let entities = self
+            .links()
+            .into_iter()
+            .chain(self.mentions())
+            .collect::<Vec<String>>();
+        classify(embeddings, settings, &txt.join("\n\n"), &entities, strict).await
+    }
+
+    fn image(&self, max_bytes: u64) -> Option<&PhotoSize> {
+        self.photo
+            .iter()
+            .flatten()
+            .chain(self.sticker.as_ref().and_then(|s| s.thumbnail.as_ref()))
+            .filter(|size| size.file_size.is_none_or(|bytes| bytes <= max_bytes))
+            .max_by_key(|size| size.width * size.height)
+    }
+
+    fn is_private(&self) -> bool {
+        self.chat.id == self.from.id
+    }
+
+    fn report(&self, detection: &Detection, action: Action) -> Report {
+        Report {
+            chat_id: self.chat.id,
+            chat_title: self.chat.title.clone(),
+            user_id: self.from.id,
+            username: self.from.username.clone(),
+            action,
+            category: detection.category.to_string(),
+            score: detection.score,
+            rules: detection.rules.clone(),
+            contents: self.contents(),
         }
-        Ok(false)
     }
 
-    async fn acknowledge(&self) -> Result<()> {
-        let client = Client::new()?;
+    async fn acknowledge(&self, client: &Client) -> Result<()> {
         client
             .set_message_reaction(self.chat.id, self.message_id)
             .await?;
         Ok(())
     }
 
-    async fn mark_as_spam(&self) -> Result<()> {
-        let client = Client::new()?;
-        if client.is_admin(self.chat.id, self.from.id).await? {
-            client
-                .set_message_reaction(self.chat.id, self.message_id)
-                .await?;
-            return Ok(());
+    async fn enforce(&self, client: &Client, action: Action, cleanup: Cleanup) -> Result<()> {
+        let chat_id = self.chat.id;
+        let user_id = self.from.id;
+        metrics()
+            .actions
+            .with_label_values(&[action.to_string().as_str()])
+            .inc();
+        match action {
+            Action::Ban => {
+                try_join!(
+                    client.delete_message(chat_id, self.message_id),
+                    client.ban_chat_member(chat_id, user_id, cleanup == Cleanup::All)
+                )?;
+            }
+            Action::Mute if !self.is_private() => {
+                try_join!(
+                    client.delete_message(chat_id, self.message_id),
+                    client.restrict_chat_member(chat_id, user_id, ChatPermissions::muted(), None)
+                )?;
+            }
+            Action::Mute | Action::Delete => {
+                client.delete_message(chat_id, self.message_id).await?;
+            }
+            Action::React => {
+                client
+                    .set_message_reaction(chat_id, self.message_id)
+                    .await?;
+            }
+            Action::Restrict if !self.is_private() => {
+                client
+                    .restrict_chat_member(chat_id, user_id, ChatPermissions::text_only(), None)
+                    .await?;
+            }
+            Action::Restrict | Action::Shadow | Action::Log | Action::Decline => {}
         }
-        try_join!(
-            client.delete_message(self.chat.id, self.message_id),
-            client.ban_chat_member(self.chat.id, self.from.id)
-        )?;
         Ok(())
     }
+
+    async fn mark_as_spam(
+        &self,
+        client: &Client,
+        detection: &Detection,
+        modlog: &ModerationLog,
+        storage: &AsyncStorage,
+        settings: &ChatSettings,
+    ) -> Result<Action> {
+        let action = if settings.shadow {
+            Action::Shadow
+        } else if client.is_admin(self.chat.id, self.from.id).await? {
+            Action::React
+        } else if detection.category == Category::SharedBan {
+            Action::Ban
+        } else {
+            settings.action
+        };
+        if !self.is_private() && action.removes_message() {
+            if let Err(e) = modlog
+                .forward(client, settings.log_chat_id, self.chat.id, self.message_id)
+                .await
+            {
+                log::error!("Error forwarding message to the moderation log: {e}");
+            }
+        }
+        self.enforce(client, action, settings.cleanup).await?;
+        if !self.is_private() {
+            let verdict = Verdict {
+                chat_id: self.chat.id,
+                user_id: self.from.id,
+                message_id: self.message_id,
+                category: detection.category.to_string(),
+                score: detection.score,
+                action,
+                contents: self.contents(),
+            };
+            if let Err(e) = storage
+                .run(move |storage| storage.record_verdict(&verdict))
+                .await
+            {
+                log::error!("Error recording verdict: {e}");
+            }
+            if detection.is_shareable() {
+                let (user_id, contents) = (self.from.id, self.contents());
+                if let Err(e) = storage
+                    .run(move |storage| remember(storage, action, user_id, contents))
+                    .await
+                {
+                    log::error!("Error remembering spam: {e}");
+                }
+            }
+            if let Err(e) = modlog
+                .report(
+                    client,
+                    storage,
+                    settings.log_chat_id,
+                    &self.report(detection, action),
+                )
+                .await
+            {
+                log::error!("Error reporting to the moderation log: {e}");
+            }
+        }
+        Ok(action)
+    }
+
+    async fn command(&self, client: &Client, storage: &AsyncStorage) -> Result<bool> {
+        let Some(text) = self.text.as_deref() else {
+            return Ok(false);
+        };
+        if self.is_private() {
+            return Ok(false);
+        }
+        commands::handle(
+            client,
+            storage,
+            self.chat.id,
+            self.chat.title.as_deref(),
+            self.message_id,
+            self.from.id,
+            text,
+        )
+        .await
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct CallbackMessage {
+    message_id: i64,
+    chat: UserOrChat,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CallbackQuery {
+    id: String,
+    from: UserOrChat,
+    message: Option<CallbackMessage>,
+    data: Option<String>,
+}
+
+impl CallbackQuery {
+    async fn handle(
+        &self,
+        client: &Client,
+        modlog: &ModerationLog,
+        storage: &AsyncStorage,
+    ) -> Result<()> {
+        let Some(data) = self.data.as_deref() else {
+            client.answer_callback_query(&self.id, "").await?;
+            return Ok(());
+        };
+        let report_id = self
+            .message
+            .as_ref()
+            .map(|msg| (msg.chat.id, msg.message_id));
+        modlog
+            .handle_callback(client, storage, &self.id, self.from.id, report_id, data)
+            .await
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ChatMember {
+    status: String,
+    user: Option<UserOrChat>,
+}
+
+impl ChatMember {
+    fn is_in_chat(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "member" | "restricted" | "administrator" | "creator"
+        )
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ChatMemberUpdated {
+    chat: UserOrChat,
+    old_chat_member: ChatMember,
+    new_chat_member: ChatMember,
+}
+
+impl ChatMemberUpdated {
+    fn changes_admins(&self) -> bool {
+        [&self.old_chat_member, &self.new_chat_member]
+            .iter()
+            .any(|member| member.status == "administrator" || member.status == "creator")
+    }
+
+    fn joined(&self) -> Option<&UserOrChat> {
+        if self.old_chat_member.is_in_chat() || !self.new_chat_member.is_in_chat() {
+            return None;
+        }
+        self.new_chat_member.user.as_ref()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ChatJoinRequest {
+    chat: UserOrChat,
+    from: UserOrChat,
+    bio: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
 struct Update {
+    update_id: i64,
     message: Option<Message>,
     edited_message: Option<Message>,
     channel_post: Option<Message>,
     edited_channel_post: Option<Message>,
     business_message: Option<Message>,
     edited_business_message: Option<Message>,
+    callback_query: Option<CallbackQuery>,
+    chat_member: Option<ChatMemberUpdated>,
+    chat_join_request: Option<ChatJoinRequest>,
     current_bot_handle: Option<String>,
 }
 
 impl Update {
-    pub async fn message(&self) -> Result<&Message> {
-        if self.is_tagging_airnope().await {
+    pub async fn message(&self, client: &Client) -> Result<&Message> {
+        if self.is_tagging_airnope(client).await {
             if let Some(msg) = self.message.as_ref() {
                 if let Some(replying_to) = msg.reply_to_message.as_ref() {
                     return Ok(replying_to);
@@ -205,15 +815,7 @@ impl Update {
         .ok_or(anyhow!("Could not find message in update payload"))
     }
 
-    async fn is_spam(&self, embeddings: Arc<Mutex<Embeddings>>) -> Result<bool> {
-        self.message().await?.is_spam(embeddings).await
-    }
-
-    async fn mark_as_spam(&self) -> Result<()> {
-        self.message().await?.mark_as_spam().await
-    }
-
-    async fn is_tagging_airnope(&self) -> bool {
+    async fn is_tagging_airnope(&self, client: &Client) -> bool {
         let mut result = false;
         if let Some(msg) = self.message.as_ref() {
             if let Some(handle) = self.current_bot_handle.as_ref() {
@@ -222,7 +824,7 @@ impl Update {
                     .as_ref()
                     .is_some_and(|txt| txt.to_lowercase().trim() == handle.to_lowercase());
                 if result {
-                    if let Err(error) = msg.acknowledge().await {
+                    if let Err(error) = msg.acknowledge(client).await {
                         log::error!("Error reacting to message tagging AirNope: {error}")
                     }
                 }
@@ -232,13 +834,750 @@ impl Update {
     }
 }
 
-async fn health() -> HttpResponse {
+#[derive(Default)]
+struct Readiness {
+    model: AtomicBool,
+    classification: AtomicBool,
+    webhook: AtomicBool,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ReadinessReport {
+    ready: bool,
+    model: bool,
+    classification: bool,
+    webhook: bool,
+}
+
+impl Readiness {
+    fn report(&self) -> ReadinessReport {
+        let model = self.model.load(Ordering::Relaxed);
+        let classification = self.classification.load(Ordering::Relaxed);
+        let webhook = self.webhook.load(Ordering::Relaxed);
+        ReadinessReport {
+            ready: model && classification && webhook,
+            model,
+            classification,
+            webhook,
+        }
+    }
+}
+
+async fn live() -> HttpResponse {
     HttpResponse::Ok().body("OK")
 }
 
+async fn ready(readiness: web::Data<Arc<Readiness>>) -> HttpResponse {
+    let report = readiness.report();
+    if report.ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+async fn prometheus() -> HttpResponse {
+    match metrics().render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        Err(e) => {
+            log::error!("Error rendering metrics: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Default)]
+struct ImageScan {
+    qr_codes: Vec<String>,
+    hash: Option<u64>,
+    image: Option<DynamicImage>,
+}
+
+#[derive(Clone, Copy)]
+struct ImageLimits {
+    max_bytes: u64,
+    timeout: Duration,
+}
+
+#[derive(Clone)]
+struct OcrStage {
+    ocr: Arc<Ocr>,
+    reading: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl OcrStage {
+    fn outcome(outcome: &str) {
+        metrics().ocr.with_label_values(&[outcome]).inc();
+    }
+
+    async fn read(&self, image: DynamicImage) -> Option<Vec<String>> {
+        let Ok(permit) = self.reading.clone().try_acquire_owned() else {
+            Self::outcome("busy");
+            return None;
+        };
+        let ocr = self.ocr.clone();
+        let task = spawn_blocking(move || {
+            let _permit = permit;
+            ocr.lines(&image)
+        });
+        match timeout(self.timeout, task).await {
+            Ok(Ok(Ok(lines))) => Some(lines),
+            Ok(Ok(Err(e))) => {
+                log::error!("Error reading text in image: {e:#}");
+                Self::outcome("failed");
+                None
+            }
+            Ok(Err(e)) => {
+                log::error!("Error reading text in image: {e}");
+                Self::outcome("failed");
+                None
+            }
+            Err(_) => {
+                log::warn!("Gave up reading text in image after {:?}", self.timeout);
+                Self::outcome("timeout");
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Worker {
+    embeddings: Arc<Mutex<Embeddings>>,
+    handle: String,
+    modlog: Arc<ModerationLog>,
+    storage: AsyncStorage,
+    client: Client,
+    check_bio: bool,
+    joins: Cache<(i64, i64), ()>,
+    probation: Cache<(i64, i64), ()>,
+    newcomers: Arc<Newcomers>,
+    raids: Arc<Raids>,
+    albums: Arc<Albums>,
+    history: Arc<History>,
+    qr_codes: bool,
+    image_limits: ImageLimits,
+    known_images: &'static KnownImages,
+    ocr: Option<OcrStage>,
+    denied_bots: Arc<Vec<String>>,
+}
+
+impl Worker {
+    async fn check_profile(
+        &self,
+        settings: &ChatSettings,
+        user: &UserOrChat,
+        bio: Option<String>,
+    ) -> Result<Option<(Detection, String)>> {
+        let profile = user
+            .profile()
+            .into_iter()
+            .chain(bio)
+            .collect::<Vec<String>>();
+        if profile.is_empty() {
+            return Ok(None);
+        }
+        let contents = profile.join("\n\n");
+        let detection = classify(self.embeddings.clone(), settings, &contents, &[], false).await?;
+        Ok(detection.map(|detection| (detection, contents)))
+    }
+
+    async fn report_member(
+        &self,
+        settings: &ChatSettings,
+        chat: &UserOrChat,
+        user: &UserOrChat,
+        action: Action,
+        detection: Detection,
+        contents: Option<String>,
+    ) {
+        let category = match detection.category {
+            Category::SharedBan => detection.category.to_string(),
+            category => format!("new member with {category} profile"),
+        };
+        let report = Report {
+            chat_id: chat.id,
+            chat_title: chat.title.clone(),
+            user_id: user.id,
+            username: user.username.clone(),
+            action,
+            category,
+            score: detection.score,
+            rules: detection.rules,
+            contents,
+        };
+        if let Err(e) = self
+            .modlog
+            .report(&self.client, &self.storage, settings.log_chat_id, &report)
+            .await
+        {
+            log::error!("Error reporting to the moderation log: {e}");
+        }
+    }
+
+    async fn check_member(&self, chat: &UserOrChat, user: &UserOrChat) -> Result<()> {
+        if user.is_bot || self.joins.contains_key(&(chat.id, user.id)) {
+            return Ok(());
+        }
+        self.joins.insert((chat.id, user.id), ()).await;
+        let chat_id = chat.id;
+        let settings = self
+            .storage
+            .run(move |storage| storage.chat_settings(chat_id))
+            .await
+            .context("Error loading chat settings")?;
+        if settings.newcomers {
+            self.newcomers.joined(chat.id, user.id).await;
+        }
+        if let Some(trigger) = self.raids.joined(chat.id).await {
+            self.alert_raid(&settings, chat, trigger).await;
+        }
+        if let Some(detection) = self.shared_ban(&settings, user.id).await? {
+            let action = if settings.shadow {
+                Action::Shadow
+            } else {
+                Action::Ban
+            };
+            metrics()
+                .actions
+                .with_label_values(&[action.to_string().as_str()])
+                .inc();
+            if action == Action::Ban {
+                self.client.ban_chat_member(chat.id, user.id, false).await?;
+            }
+            self.report_member(&settings, chat, user, action, detection, None)
+                .await;
+            return Ok(());
+        }
+        if !settings.shadow && self.raids.is_raided(chat.id).await {
+            metrics()
+                .actions
+                .with_label_values(&[Action::Restrict.to_string().as_str()])
+                .inc();
+            self.client
+                .restrict_chat_member(
+                    chat.id,
+                    user.id,
+                    ChatPermissions::text_only(),
+                    Some(PROBATION_TTL),
+                )
+                .await?;
+            self.probation.insert((chat.id, user.id), ()).await;
+        }
+        let Some(join) = settings.join else {
+            return Ok(());
+        };
+        let mut bio = None;
+        if self.check_bio {
+            match self.client.bio(user.id).await {
+                Ok(value) => bio = value,
+                Err(e) => log::error!("Error getting the bio of {}: {e:#}", user.id),
+            }
+        }
+        let Some((detection, contents)) = self.check_profile(&settings, user, bio).await? else {
+            return Ok(());
+        };
+        let action = if settings.shadow {
+            Action::Shadow
+        } else {
+            Action::from(join)
+        };
+        metrics()
+            .actions
+            .with_label_values(&[action.to_string().as_str()])
+            .inc();
+        match action {
+            Action::Ban => {
+                self.client.ban_chat_member(chat.id, user.id, false).await?;
+                if detection.is_shareable() {
+                    let user_id = user.id;
+                    self.storage
+                        .run(move |storage| storage.remember_spammer(user_id))
+                        .await?;
+                }
+            }
+            Action::Restrict => {
+                self.client
+                    .restrict_chat_member(
+                        chat.id,
+                        user.id,
+                        ChatPermissions::text_only(),
+                        Some(PROBATION_TTL),
+                    )
+                    .await?;
+                self.probation.insert((chat.id, user.id), ()).await;
+            }
+            _ => {}
+        }
+        self.report_member(&settings, chat, user, action, detection, Some(contents))
+            .await;
+        Ok(())
+    }
+
+    async fn check_join_request(&self, request: &ChatJoinRequest) -> Result<()> {
+        let (chat, user) = (&request.chat, &request.from);
+        let chat_id = chat.id;
+        let settings = self
+            .storage
+            .run(move |storage| storage.chat_settings(chat_id))
+            .await
+            .context("Error loading chat settings")?;
+        match self
+            .check_profile(&settings, user, request.bio.clone())
+            .await?
+        {
+            Some((detection, contents)) => {
+                let action = if settings.shadow {
+                    Action::Shadow
+                } else {
+                    Action::Decline
+                };
+                metrics()
+                    .actions
+                    .with_label_values(&[action.to_string().as_str()])
+                    .inc();
+                if action == Action::Decline {
+                    self.client
+                        .decline_chat_join_request(chat.id, user.id)
+                        .await?;
+                }
+                self.report_member(&settings, chat, user, action, detection, Some(contents))
+                    .await;
+            }
+            None if settings.approve_requests && !settings.shadow => {
+                self.client
+                    .approve_chat_join_request(chat.id, user.id)
+                    .await?;
+                log::info!("Approved join request from {} to {}", user.id, chat.id);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    async fn alert_raid(&self, settings: &ChatSettings, chat: &UserOrChat, trigger: Trigger) {
+        log::warn!("Raid detected in {}: {trigger}", chat.id);
+        let name = match &chat.title {
+            Some(title) => format!("{title} ({})", chat.id),
+            None => chat.id.to_string(),
+        };
+        let measures = if settings.shadow {
+            "none (shadow mode)".to_string()
+        } else {
+            format!(
+                "every message goes through the classifier with the threshold lowered to {:.2}, and new members are restricted to text until their first message is checked",
+                raid_threshold(settings)
+            )
+        };
+        let text = format!(
+            "🚨 AirNope raid alert\nChat: {name}\nTrigger: {trigger}\nFor the next {} minutes: {measures}",
+            self.raids.duration().as_secs() / 60
+        );
+        if let Err(e) = self
+            .modlog
+            .notify(&self.client, settings.log_chat_id, &text)
+            .await
+        {
+            log::error!("Error reporting raid to the moderation log: {e}");
+        }
+    }
+
+    async fn shared_ban(&self, settings: &ChatSettings, user_id: i64) -> Result<Option<Detection>> {
+        if !settings.shared_bans
+            || !self
+                .storage
+                .run(move |storage| storage.is_known_spammer(user_id))
+                .await?
+        {
+            return Ok(None);
+        }
+        metrics().verdicts.with_label_values(&["shared_ban"]).inc();
+        Ok(Some(Detection {
+            category: Category::SharedBan,
+            score: None,
+            rules: vec![],
+        }))
+    }
+
+    async fn recall(&self, message: &Message) -> Result<Option<Detection>> {
+        let Some(fingerprint) = message.contents().as_deref().and_then(simhash) else {
+            return Ok(None);
+        };
+        if !self
+            .storage
+            .run(move |storage| storage.is_known_spam(fingerprint))
+            .await?
+        {
+            return Ok(None);
+        }
+        metrics().verdicts.with_label_values(&["fingerprint"]).inc();
+        Ok(Some(Detection {
+            category: Category::Repeat,
+            score: None,
+            rules: vec!["fingerprint".to_string()],
+        }))
+    }
+
+    async fn remove_album(&self, message: &Message, action: Action) -> Result<()> {
+        let Some(media_group_id) = message.media_group_id.as_deref() else {
+            return Ok(());
+        };
+        if !action.removes_message() {
+            return Ok(());
+        }
+        let message_ids = self
+            .albums
+            .remove(message.chat.id, media_group_id)
+            .await
+            .into_iter()
+            .filter(|id| *id != message.message_id)
+            .collect::<Vec<i64>>();
+        if !message_ids.is_empty() {
+            self.client
+                .delete_messages(message.chat.id, &message_ids)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn clean_up(&self, message: &Message, action: Action, cleanup: Cleanup) -> Result<()> {
+        let key = (message.chat.id, message.from.id);
+        let message_ids = self.history.take(key.0, key.1).await;
+        if action != Action::Ban || cleanup != Cleanup::Recent {
+            return Ok(());
+        }
+        let message_ids = message_ids
+            .into_iter()
+            .filter(|id| *id != message.message_id)
+            .collect::<Vec<i64>>();
+        if !message_ids.is_empty() {
+            self.client.delete_messages(key.0, &message_ids).await?;
+            log::info!(
+                "Deleted {} recent messages from {} in {}",
+                message_ids.len(),
+                key.1,
+                key.0
+            );
+        }
+        Ok(())
+    }
+
+    async fn read_image(&self, image: &PhotoSize) -> Result<ImageScan> {
+        let bytes = self
+            .client
+            .download(&image.file_id, self.image_limits.max_bytes)
+            .await?;
+        let qr_codes = self.qr_codes;
+        let keep = self.ocr.is_some();
+        spawn_blocking(move || {
+            let image = images::decode(&bytes)?;
+            Ok(ImageScan {
+                qr_codes: if qr_codes {
+                    images::qr_codes(&image)
+                } else {
+                    vec![]
+                },
+                hash: Some(images::dhash(&image)),
+                image: keep.then_some(image),
+            })
+        })
+        .await?
+    }
+
+    async fn scan_image(&self, message: &Message) -> ImageScan {
+        if !self.qr_codes && self.known_images.is_empty() && self.ocr.is_none() {
+            return ImageScan::default();
+        }
+        let Some(image) = message.image(self.image_limits.max_bytes) else {
+            return ImageScan::default();
+        };
+        match timeout(self.image_limits.timeout, self.read_image(image)).await {
+            Ok(Ok(scan)) => scan,
+            Ok(Err(e)) => {
+                log::error!("Error reading image: {e:#}");
+                ImageScan::default()
+            }
+            Err(_) => {
+                log::warn!(
+                    "Gave up reading image after {:?}",
+                    self.image_limits.timeout
+                );
+                ImageScan::default()
+            }
+        }
+    }
+
+    fn known_image(&self, scan: &ImageScan) -> Option<Detection> {
+        if !scan
+            .hash
+            .is_some_and(|hash| self.known_images.contains(hash))
+        {
+            return None;
+        }
+        metrics().verdicts.with_label_values(&["known_image"]).inc();
+        Some(Detection {
+            category: Category::KnownImage,
+            score: None,
+            rules: vec!["image_hash".to_string()],
+        })
+    }
+
+    async fn detect(
+        &self,
+        message: &Message,
+        settings: &ChatSettings,
+        strict: bool,
+        scan: ImageScan,
+    ) -> Result<Option<Detection>> {
+        let detection = message
+            .detect(
+                self.embeddings.clone(),
+                settings,
+                strict,
+                &scan.qr_codes,
+                &self.denied_bots,
+            )
+            .await?;
+        if detection.is_some() {
+            return Ok(detection);
+        }
+        let (Some(stage), Some(image)) = (self.ocr.as_ref(), scan.image) else {
+            return Ok(None);
+        };
+        let Some(lines) = stage.read(image).await else {
+            return Ok(None);
+        };
+        if lines.is_empty() {
+            OcrStage::outcome("no_text");
+            return Ok(None);
+        }
+        let extra = scan.qr_codes.into_iter().chain(lines).collect::<Vec<_>>();
+        let detection = message
+            .detect(
+                self.embeddings.clone(),
+                settings,
+                strict,
+                &extra,
+                &self.denied_bots,
+            )
+            .await?;
+        OcrStage::outcome(if detection.is_some() {
+            "changed"
+        } else {
+            "unchanged"
+        });
+        Ok(detection)
+    }
+
+    async fn end_probation(&self, message: &Message) -> Result<()> {
+        let key = (message.chat.id, message.from.id);
+        if self.probation.remove(&key).await.is_some() {
+            self.client
+                .restrict_chat_member(key.0, key.1, ChatPermissions::unrestricted(), None)
+                .await?;
+            log::info!("Lifted restrictions on {} in {}", key.1, key.0);
+        }
+        Ok(())
+    }
+
+    async fn process(&self, update: &Update) -> Result<()> {
+        let client = &self.client;
+        let storage = &self.storage;
+        if let Some(member) = update.chat_member.as_ref() {
+            if member.changes_admins() {
+                client.invalidate_admins(member.chat.id).await;
+            }
+            if let Some(user) = member.joined() {
+                self.check_member(&member.chat, user)
+                    .await
+                    .context("Error checking new member")?;
+            }
+            return Ok(());
+        }
+        if let Some(request) = update.chat_join_request.as_ref() {
+            return self
+                .check_join_request(request)
+                .await
+                .context("Error checking join request");
+        }
+        if let Some(query) = update.callback_query.as_ref() {
+            return query
+                .handle(client, &self.modlog, storage)
+                .await
+                .context("Error handling callback query");
+        }
+        if let Some(msg) = update.message.as_ref() {
+            if let Some(users) = msg.new_chat_members.as_ref() {
+                for user in users {
+                    self.check_member(&msg.chat, user)
+                        .await
+                        .context("Error checking new member")?;
+                }
+                return Ok(());
+            }
+            if msg
+                .command(client, storage)
+                .await
+                .context("Error handling AirNope command")?
+            {
+                return Ok(());
+            }
+        }
+        let message = update.message(client).await?;
+        let chat_id = message.chat.id;
+        let mut chat_settings = storage
+            .run(move |storage| storage.chat_settings(chat_id))
+            .await
+            .context("Error loading chat settings")?;
+        if let Some(media_group_id) = message.media_group_id.as_deref() {
+            if self
+                .albums
+                .add(message.chat.id, media_group_id, message.message_id)
+                .await
+            {
+                client
+                    .delete_message(message.chat.id, message.message_id)
+                    .await
+                    .context("Error deleting album item")?;
+                return Ok(());
+            }
+        }
+        if !message.is_private() {
+            self.history
+                .record(message.chat.id, message.from.id, message.message_id)
+                .await;
+            let fingerprint = message.contents().as_deref().and_then(simhash);
+            if let Some(trigger) = self.raids.message(message.chat.id, fingerprint).await {
+                self.alert_raid(&chat_settings, &message.chat, trigger)
+                    .await;
+            }
+        }
+        let raided = self.raids.is_raided(message.chat.id).await;
+        if raided {
+            chat_settings.threshold = Some(raid_threshold(&chat_settings));
+        }
+        let newcomer = chat_settings.newcomers
+            && self
+                .newcomers
+                .is_new(message.chat.id, message.from.id)
+                .await;
+        let strict = raided || newcomer;
+        let known = match self.shared_ban(&chat_settings, message.from.id).await? {
+            Some(detection) => Some(detection),
+            None => self
+                .recall(message)
+                .await
+                .context("Error checking spam fingerprints")?,
+        };
+        let detection = match known {
+            Some(detection) => Some(detection),
+            None => {
+                let scan = self.scan_image(message).await;
+                match self.known_image(&scan) {
+                    Some(detection) => Some(detection),
+                    None => self
+                        .detect(message, &chat_settings, strict, scan)
+                        .await
+                        .context("Error checking if message is spam")?,
+                }
+            }
+        };
+        match detection {
+            Some(detection) => {
+                let action = message
+                    .mark_as_spam(client, &detection, &self.modlog, storage, &chat_settings)
+                    .await
+                    .context("Error marking message as spam")?;
+                self.remove_album(message, action)
+                    .await
+                    .context("Error deleting album")?;
+                self.clean_up(message, action, chat_settings.cleanup)
+                    .await
+                    .context("Error deleting recent messages")?;
+            }
+            None => self
+                .end_probation(message)
+                .await
+                .context("Error lifting restrictions")?,
+        }
+        Ok(())
+    }
+
+    async fn work(&self, mut update: Update, received: Instant) {
+        update.current_bot_handle = Some(self.handle.clone());
+        let _timer = HandlingTimer(received);
+        if let Err(e) = self.process(&update).await {
+            log::error!("{e:#} (giving up on update {})", update.update_id);
+        }
+    }
+}
+
+struct HandlingTimer(Instant);
+
+impl Drop for HandlingTimer {
+    fn drop(&mut self) {
+        metrics()
+            .handling_seconds
+            .observe(self.0.elapsed().as_secs_f64());
+    }
+}
+
+struct Queue {
+    sender: mpsc::Sender<(Update, Instant)>,
+    recent: Cache<i64, ()>,
+}
+
+impl Queue {
+    fn depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    async fn push(&self, update: Update) -> Result<bool> {
+        let update_id = update.update_id;
+        if !self.recent.entry(update_id).or_insert(()).await.is_fresh() {
+            return Ok(false);
+        }
+        if let Err(e) = self.sender.try_send((update, Instant::now())) {
+            self.recent.invalidate(&update_id).await;
+            return Err(anyhow!("Could not queue update {update_id}: {e}"));
+        }
+        metrics().queue_depth.inc();
+        Ok(true)
+    }
+}
+
+fn spawn_workers(
+    count: usize,
+    receiver: mpsc::Receiver<(Update, Instant)>,
+    worker: Worker,
+) -> Vec<JoinHandle<()>> {
+    let receiver = Arc::new(Mutex::new(receiver));
+    (0..count)
+        .map(|_| {
+            let receiver = receiver.clone();
+            let worker = worker.clone();
+            tokio::spawn(async move {
+                loop {
+                    let update = receiver.lock().await.recv().await;
+                    match update {
+                        Some((update, received)) => {
+                            metrics().queue_depth.dec();
+                            worker.work(update, received).await
+                        }
+                        None => break,
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
 async fn handler(
-    embeddings: web::Data<Arc<Mutex<Embeddings>>>,
     settings: web::Data<Arc<Settings>>,
+    queue: web::Data<Arc<Queue>>,
     request: HttpRequest,
     body: Bytes,
 ) -> HttpResponse {
@@ -250,56 +1589,236 @@ async fn handler(
     if settings.secret.as_str() != token {
         return HttpResponse::Unauthorized().finish();
     }
-    match serde_json::from_slice::<Update>(&body) {
+    let update = match serde_json::from_slice::<Update>(&body) {
+        Ok(update) => update,
         Err(e) => {
             log::error!(
                 "Error parsing update: {}\n{}",
                 e,
                 String::from_utf8_lossy(&body)
             );
-            HttpResponse::BadRequest().finish()
-        }
-        Ok(mut update) => {
-            update.current_bot_handle = Some(settings.handle.clone());
-            match update.is_spam(embeddings.get_ref().clone()).await {
-                Ok(false) => HttpResponse::Ok().finish(),
-                Ok(true) => {
-                    if let Err(e) = update.mark_as_spam().await {
-                        log::error!("Error marking message as spam: {e}");
-                        return HttpResponse::InternalServerError().finish();
-                    }
-                    HttpResponse::Ok().finish()
-                }
-                Err(e) => {
-                    log::error!("Error checking if message is spam: {e}");
-                    HttpResponse::InternalServerError().finish()
+            return HttpResponse::Ok().finish();
+        }
+    };
+    let update_id = update.update_id;
+    metrics().updates_received.inc();
+    match queue.push(update).await {
+        Ok(true) => {
+            log::debug!("Update {update_id} queued ({} pending)", queue.depth());
+            HttpResponse::Ok().finish()
+        }
+        Ok(false) => {
+            log::debug!("Skipping update {update_id}, it was already received");
+            HttpResponse::Ok().finish()
+        }
+        Err(e) => {
+            log::warn!("{e}");
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T>
+where
+    T::Err: fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse::<T>()
+            .map_err(|e| anyhow!("Invalid value for {name}: {e}")),
+        Err(_) => Ok(default),
+    }
+}
+
+fn at_least_one(name: &str, value: usize) -> Result<usize> {
+    if value < 1 {
+        return Err(anyhow!("Invalid value for {name}: it must be at least 1"));
+    }
+    Ok(value)
+}
+
+async fn start(
+    settings: &Settings,
+    readiness: &Readiness,
+    workers: usize,
+    receiver: mpsc::Receiver<(Update, Instant)>,
+) -> Result<(Client, Vec<JoinHandle<()>>)> {
+    let embeddings = Arc::new(Mutex::new(Embeddings::new().await?));
+    readiness.model.store(true, Ordering::Relaxed);
+    let classifier = ZeroShotClassification::default(&embeddings).await?;
+    is_spam_with_custom_classifier(&embeddings, classifier, READINESS_PROBE, &[])
+        .await
+        .context("Error running test classification")?;
+    readiness.classification.store(true, Ordering::Relaxed);
+    let ocr_timeout = Duration::from_secs(env_or(OCR_TIMEOUT, DEFAULT_OCR_TIMEOUT)?);
+    let ocr = Ocr::from_env()
+        .context("Error loading OCR models")?
+        .map(|ocr| OcrStage {
+            ocr: Arc::new(ocr),
+            reading: Arc::new(Semaphore::new(1)),
+            timeout: ocr_timeout,
+        });
+    let worker = Worker {
+        embeddings,
+        handle: settings.handle.clone(),
+        modlog: Arc::new(ModerationLog::new()?),
+        storage: AsyncStorage::new(Sqlite::new()?),
+        client: Client::new()?,
+        check_bio: env::var(CHECK_BIO).is_ok_and(|value| value == "true"),
+        joins: Cache::builder()
+            .max_capacity(RECENT_JOINS)
+            .time_to_live(RECENT_JOINS_TTL)
+            .build(),
+        probation: Cache::builder()
+            .max_capacity(PROBATION_SIZE)
+            .time_to_live(PROBATION_TTL)
+            .build(),
+        newcomers: Arc::new(Newcomers::new(
+            env_or(NEWCOMER_MESSAGES, DEFAULT_NEWCOMER_MESSAGES)?,
+            Duration::from_secs(60 * env_or(NEWCOMER_MINUTES, DEFAULT_NEWCOMER_MINUTES)?),
+        )),
+        raids: Arc::new(Raids::new(Limits {
+            messages: env_or(RAID_MESSAGES, DEFAULT_RAID_MESSAGES)?,
+            joins: env_or(RAID_JOINS, DEFAULT_RAID_JOINS)?,
+            similar: env_or(RAID_SIMILAR, DEFAULT_RAID_SIMILAR)?,
+            duration: Duration::from_secs(60 * env_or(RAID_MINUTES, DEFAULT_RAID_MINUTES)?),
+        })),
+        albums: Arc::new(Albums::new()),
+        history: Arc::new(History::new(HISTORY_TTL)),
+        qr_codes: env::var(QR_CODES).is_ok_and(|value| value == "true"),
+        image_limits: ImageLimits {
+            max_bytes: env_or(IMAGE_MAX_BYTES, DEFAULT_IMAGE_MAX_BYTES)?,
+            timeout: Duration::from_secs(env_or(IMAGE_TIMEOUT, DEFAULT_IMAGE_TIMEOUT)?),
+        },
+        known_images: KnownImages::shared().await?,
+        ocr,
+        denied_bots: Arc::new(denied_bots(
+            &env::var(DENIED_BOTS).unwrap_or_else(|_| DEFAULT_DENIED_BOTS.to_string()),
+        )),
+    };
+    let client = worker.client.clone();
+    let handles = spawn_workers(workers, receiver, worker);
+    client.delete_webhook().await?;
+    if !client.set_webhook(settings.secret.as_str()).await? {
+        return Err(anyhow!("Telegram did not register the webhook"));
+    }
+    readiness.webhook.store(true, Ordering::Relaxed);
+    Ok((client, handles))
+}
+
+async fn drain(queue: Arc<Queue>, handles: Vec<JoinHandle<()>>, deadline: Duration) {
+    log::info!("Draining {} queued updates", queue.depth());
+    drop(queue);
+    let aborts: Vec<_> = handles.iter().map(|h| h.abort_handle()).collect();
+    match timeout(deadline, join_all(handles)).await {
+        Ok(results) => {
+            for result in results {
+                if let Err(e) = result {
+                    log::error!("Error in worker: {e}");
                 }
             }
+            log::info!("All queued updates processed");
+        }
+        Err(_) => {
+            log::warn!(
+                "Gave up on {} queued updates after {deadline:?}",
+                metrics().queue_depth.get()
+            );
+            for abort in aborts {
+                abort.abort();
+            }
         }
     }
 }
 
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    select! {
+        _ = terminate.recv() => {},
+        result = ctrl_c() => result?,
+    }
+    Ok(())
+}
+
 pub async fn run() -> Result<()> {
     let port = env::var("PORT")
         .unwrap_or(DEFAULT_PORT.to_string())
         .parse::<u16>()?;
-    let embeddings = Arc::new(Mutex::new(Embeddings::new().await?));
-    let client = Client::new()?;
-    let settings = Settings::new();
-    client.delete_webhook().await?;
-    client.set_webhook(settings.secret.as_str()).await?;
-    HttpServer::new(move || {
+    let queue_size = at_least_one(QUEUE_SIZE, env_or(QUEUE_SIZE, DEFAULT_QUEUE_SIZE)?)?;
+    let workers = at_least_one(WORKERS, env_or(WORKERS, DEFAULT_WORKERS)?)?;
+    let shutdown_timeout = Duration::from_secs(env_or(SHUTDOWN_TIMEOUT, DEFAULT_SHUTDOWN_TIMEOUT)?);
+    let delete_webhook = env::var(DELETE_WEBHOOK_ON_SHUTDOWN).is_ok_and(|value| value == "true");
+    let settings = Arc::new(Settings::new());
+    let readiness = Arc::new(Readiness::default());
+    let recent: Cache<i64, ()> = Cache::builder()
+        .max_capacity(RECENT_UPDATES)
+        .time_to_live(RECENT_UPDATES_TTL)
+        .build();
+    let (sender, receiver) = mpsc::channel(queue_size);
+    let queue = Arc::new(Queue { sender, recent });
+    let server_settings = settings.clone();
+    let server_readiness = readiness.clone();
+    let server_queue = queue.clone();
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
-            .app_data(web::Data::new(embeddings.clone()))
-            .app_data(web::Data::new(Arc::new(settings.clone())))
+            .app_data(web::Data::new(server_settings.clone()))
+            .app_data(web::Data::new(server_readiness.clone()))
+            .app_data(web::Data::new(server_queue.clone()))
             .route("/", web::post().to(handler))
-            .route("/health", web::get().to(health))
+            .route("/health", web::get().to(live))
+            .route("/health/live", web::get().to(live))
+            .route("/health/ready", web::get().to(ready))
+            .route("/metrics", web::get().to(prometheus))
     })
     .workers(32)
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .bind((DEFAULT_HOST_IP, port))?
-    .run()
-    .await?;
+    .run();
+    let server_handle = server.handle();
+    let mut server = tokio::spawn(server);
+
+    let (client, handles) = match start(&settings, &readiness, workers, receiver).await {
+        Ok(started) => started,
+        Err(e) => {
+            server_handle.stop(false).await;
+            return Err(e);
+        }
+    };
+    log::info!("Processing updates with {workers} workers (queue size {queue_size})");
+    let stopping = select! {
+        result = shutdown_signal() => {
+            result?;
+            true
+        }
+        result = &mut server => {
+            result??;
+            false
+        }
+    };
+
+    let started = Instant::now();
+    if stopping {
+        server_handle.stop(true).await;
+        server.await??;
+    }
+    if delete_webhook {
+        if let Err(e) = client.delete_webhook().await {
+            log::error!("Error deleting webhook: {e}");
+        }
+    }
+    drain(
+        queue,
+        handles,
+        shutdown_timeout.saturating_sub(started.elapsed()),
+    )
+    .await;
+    match metrics().render() {
+        Ok(metrics) => log::debug!("Metrics at shutdown:\n{metrics}"),
+        Err(e) => log::error!("Error rendering metrics: {e}"),
+    }
+    log::logger().flush();
     Ok(())
 }
 
@@ -312,7 +1831,9 @@ pub async fn remove() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::Memory;
     use std::fs;
+    use tokio::time::sleep;
 
     #[test]
     fn test_deserialize_message() {
@@ -326,6 +1847,112 @@ mod tests {
             .is_none());
     }
 
+    #[test]
+    fn test_deserialize_album_item() {
+        let data = fs::read_to_string("test_data/message_album.json").unwrap();
+        let message: Message = serde_json::from_str(&data).unwrap();
+        assert_eq!(message.media_group_id.as_deref(), Some("13954471212453890"));
+        assert!(message.contents().is_none());
+    }
+
+    #[test]
+    fn test_largest_image_within_limit() {
+        let data = fs::read_to_string("test_data/message_photo.json").unwrap();
+        let message: Message = serde_json::from_str(&data).unwrap();
+        let file_id = |max_bytes| message.image(max_bytes).map(|size| size.file_id.as_str());
+        assert_eq!(file_id(1_048_576), Some("AgACAgEAAxkBAAIBa2aQ1-x"));
+        assert_eq!(file_id(10_000), Some("AgACAgEAAxkBAAIBa2aQ1-m"));
+        assert_eq!(file_id(1_000), None);
+        let data = fs::read_to_string("test_data/message_sticker.json").unwrap();
+        let message: Message = serde_json::from_str(&data).unwrap();
+        assert_eq!(
+            message.image(1_048_576).map(|size| size.file_id.as_str()),
+            Some("AAMCAQADGQEAAgFtZpDV-t")
+        );
+    }
+
+    #[test]
+    fn test_contents_of_polls_contacts_venues_and_quotes() {
+        let test_cases = vec![
+            (
+                "message_poll.json",
+                "Claim your $NOT airdrop before the snapshot?\nYes, connect my wallet\nNo, I will miss the free tokens",
+            ),
+            (
+                "message_contact.json",
+                "Airdrop Support Claim Free USDT\n+15550100123",
+            ),
+            (
+                "message_venue.json",
+                "Free crypto airdrop meetup\nClaim tokens at t.me/airdrop_claim_bot",
+            ),
+            (
+                "message_quote.json",
+                "Is this legit?\n\nConnect your wallet to receive 500 USDT\n\nOfficial Airdrop Claims",
+            ),
+        ];
+        for (path, expected) in test_cases {
+            let data = fs::read_to_string(format!("test_data/{path}")).unwrap();
+            let message: Message = serde_json::from_str(&data).unwrap();
+            assert_eq!(message.contents().unwrap(), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_message_entities() {
+        let data = fs::read_to_string("test_data/message_text_link.json").unwrap();
+        let message: Message = serde_json::from_str(&data).unwrap();
+        assert_eq!(
+            message.links(),
+            vec!["https://claim-reward.pages.dev/?ref=91472"]
+        );
+        assert_eq!(message.mentions(), vec!["@maria_airdrop_support"]);
+        let contents = message.contents().unwrap();
+        assert!(contents
+            .ends_with("\n\nhttps://claim-reward.pages.dev/?ref=91472\n@maria_airdrop_support"));
+
+        let data = fs::read_to_string("test_data/message_safeguard.json").unwrap();
+        let message: Message = serde_json::from_str(&data).unwrap();
+        assert!(message.links().is_empty());
+        assert!(message.mentions().is_empty());
+    }
+
+    #[test]
+    fn test_message_via_denied_bot() {
+        let data = fs::read_to_string("test_data/message_via_bot.json").unwrap();
+        let message: Message = serde_json::from_str(&data).unwrap();
+        assert_eq!(message.denied_bot(&denied_bots(DEFAULT_DENIED_BOTS)), None);
+        assert_eq!(
+            message.denied_bot(&denied_bots("safeguard, @AirdropClaimBot")),
+            Some("airdropclaimbot".to_string())
+        );
+        assert!(message
+            .contents()
+            .unwrap()
+            .contains("https://claim-not.pages.dev/?ref=7731"));
+
+        let data = fs::read_to_string("test_data/message_safeguard.json").unwrap();
+        let message: Message = serde_json::from_str(&data).unwrap();
+        assert_eq!(
+            message.denied_bot(&denied_bots(DEFAULT_DENIED_BOTS)),
+            Some("safeguard".to_string())
+        );
+        assert_eq!(message.denied_bot(&denied_bots("")), None);
+    }
+
+    #[test]
+    fn test_entity_text_uses_utf16_offsets() {
+        let entity = MessageEntity {
+            kind: "url".to_string(),
+            offset: 3,
+            length: 8,
+            url: None,
+            user: None,
+        };
+        assert_eq!(entity.text("🎁 t.me/bot").unwrap(), "t.me/bot");
+        assert_eq!(entity.text("🎁 t.me"), None);
+    }
+
     #[test]
     fn test_deserialize_message_with_sender() {
         let data = fs::read_to_string("test_data/message_safeguard.json").unwrap();
@@ -341,11 +1968,196 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_deserialize_callback_query() {
+        let data = fs::read_to_string("test_data/update_callback_query.json").unwrap();
+        let update: Update = serde_json::from_str(&data).unwrap();
+        let query = update.callback_query.unwrap();
+        assert_eq!(query.from.id, 2257484);
+        assert_eq!(query.message.unwrap().message_id, 1337);
+        assert_eq!(query.data.unwrap(), "ham:-1001061765207:6873835434");
+    }
+
+    #[test]
+    fn test_readiness_report() {
+        let readiness = Readiness::default();
+        readiness.model.store(true, Ordering::Relaxed);
+        let expected = ReadinessReport {
+            ready: false,
+            model: true,
+            classification: false,
+            webhook: false,
+        };
+        assert_eq!(readiness.report(), expected);
+        readiness.classification.store(true, Ordering::Relaxed);
+        readiness.webhook.store(true, Ordering::Relaxed);
+        assert!(readiness.report().ready);
+    }
+
+    #[test]
+    fn test_at_least_one() {
+        assert_eq!(at_least_one(WORKERS, 8).unwrap(), 8);
+        assert!(at_least_one(WORKERS, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_gives_up_after_deadline() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let queue = Arc::new(Queue {
+            sender,
+            recent: Cache::new(1),
+        });
+        let stuck = tokio::spawn(sleep(Duration::from_secs(60)));
+        let abort = stuck.abort_handle();
+        let started = Instant::now();
+        drain(queue, vec![stuck], Duration::from_millis(10)).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        sleep(Duration::from_millis(10)).await;
+        assert!(abort.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_queue_rejects_updates_when_full() {
+        let update = || {
+            let data = fs::read_to_string("test_data/update_callback_query.json").unwrap();
+            serde_json::from_str::<Update>(&data).unwrap()
+        };
+        let (sender, _receiver) = mpsc::channel(1);
+        let queue = Queue {
+            sender,
+            recent: Cache::new(1),
+        };
+        assert_eq!(queue.depth(), 0);
+        assert!(queue.sender.try_send((update(), Instant::now())).is_ok());
+        assert_eq!(queue.depth(), 1);
+        assert!(queue.sender.try_send((update(), Instant::now())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_queue_skips_updates_already_received() {
+        let update = |update_id| {
+            let data = fs::read_to_string("test_data/update_callback_query.json").unwrap();
+            let mut update = serde_json::from_str::<Update>(&data).unwrap();
+            update.update_id = update_id;
+            update
+        };
+        let (sender, mut receiver) = mpsc::channel(1);
+        let queue = Queue {
+            sender,
+            recent: Cache::new(16),
+        };
+        assert!(queue.push(update(1)).await.unwrap());
+        assert!(!queue.push(update(1)).await.unwrap());
+        assert!(queue.push(update(2)).await.is_err());
+        receiver.recv().await.unwrap();
+        assert!(queue.push(update(2)).await.unwrap());
+    }
+
+    #[test]
+    fn test_remember_only_removed_spam() {
+        let storage = Memory::new();
+        let spam = "Claim your free $NOT tokens before the snapshot at claim-not.pages.dev";
+        let fingerprint = simhash(spam).unwrap();
+        remember(&storage, Action::Shadow, 42, Some(spam.to_string())).unwrap();
+        assert!(!storage.is_known_spam(fingerprint).unwrap());
+        remember(&storage, Action::Delete, 42, Some(spam.to_string())).unwrap();
+        assert!(storage.is_known_spam(fingerprint).unwrap());
+        assert!(!storage.is_known_spammer(42).unwrap());
+        remember(&storage, Action::Ban, 42, None).unwrap();
+        assert!(storage.is_known_spammer(42).unwrap());
+    }
+
+    #[test]
+    fn test_only_detections_above_the_default_threshold_are_shared() {
+        let detection = |category, score| Detection {
+            category,
+            score,
+            rules: vec![],
+        };
+        assert!(detection(Category::Airdrop, Some(THRESHOLD + 0.1)).is_shareable());
+        assert!(detection(Category::KnownImage, None).is_shareable());
+        assert!(!detection(Category::Airdrop, Some(0.02)).is_shareable());
+        assert!(!detection(Category::SharedBan, None).is_shareable());
+    }
+
+    #[test]
+    fn test_raid_threshold() {
+        let mut settings = ChatSettings::default();
+        assert_eq!(raid_threshold(&settings), 0.4);
+        settings.threshold = Some(0.15);
+        assert_eq!(raid_threshold(&settings), RAID_THRESHOLD_DROP);
+    }
+
+    #[test]
+    fn test_chat_member_promotion_changes_admins() {
+        let data = fs::read_to_string("test_data/update_chat_member.json").unwrap();
+        let update: Update = serde_json::from_str(&data).unwrap();
+        let mut member = update.chat_member.unwrap();
+        assert_eq!(member.chat.id, -1001061765207);
+        assert!(member.changes_admins());
+        member.new_chat_member.status = "kicked".to_string();
+        assert!(!member.changes_admins());
+    }
+
+    #[test]
+    fn test_chat_member_joined() {
+        let data = fs::read_to_string("test_data/update_chat_member.json").unwrap();
+        let update: Update = serde_json::from_str(&data).unwrap();
+        let mut member = update.chat_member.unwrap();
+        assert!(member.joined().is_none());
+        member.old_chat_member.status = "left".to_string();
+        member.new_chat_member.status = "member".to_string();
+        assert_eq!(member.joined().unwrap().id, 6873835434);
+    }
+
+    #[test]
+    fn test_deserialize_chat_join_request() {
+        let data = fs::read_to_string("test_data/update_chat_join_request.json").unwrap();
+        let update: Update = serde_json::from_str(&data).unwrap();
+        let request = update.chat_join_request.unwrap();
+        assert_eq!(request.chat.id, -1001061765207);
+        assert_eq!(
+            request.from.profile().unwrap(),
+            "Claim $NOT Airdrop @notcoin_rewards_hub"
+        );
+        assert!(request
+            .bio
+            .unwrap()
+            .starts_with("🎁 Claim your free tokens"));
+    }
+
+    #[test]
+    fn test_new_chat_members_profile() {
+        let data = fs::read_to_string("test_data/update_new_chat_members.json").unwrap();
+        let update: Update = serde_json::from_str(&data).unwrap();
+        let members = update.message.unwrap().new_chat_members.unwrap();
+        assert_eq!(members.len(), 1);
+        assert!(!members[0].is_bot);
+        assert_eq!(
+            members[0].profile().unwrap(),
+            "Airdrop Support 🎁 @airdrop_claim_support"
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_message_from_safeguard_is_spam() {
         let embeddings = Arc::new(Mutex::new(Embeddings::new().await.unwrap()));
         let data = fs::read_to_string("test_data/message_safeguard.json").unwrap();
         let message: Message = serde_json::from_str(&data).unwrap();
-        assert!(message.is_spam(embeddings).await.unwrap());
+        let detection = message
+            .detect(
+                embeddings,
+                &ChatSettings::default(),
+                false,
+                &[],
+                &denied_bots(DEFAULT_DENIED_BOTS),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            detection.category,
+            Category::DeniedBot("safeguard".to_string())
+        );
     }
 }